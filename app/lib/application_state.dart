@@ -1,6 +1,7 @@
 import 'package:app/generated/moneyview.pbgrpc.dart';
 import 'package:english_words/english_words.dart';
 import 'package:flutter/material.dart';
+import 'package:grpc/grpc.dart';
 
 import 'grpc_channel.dart' if (dart.library.html) 'grpc_channel_web.dart';
 
@@ -9,7 +10,31 @@ class ApplicationState extends ChangeNotifier {
 
   var favorites = <WordPair>[];
 
-  dynamic moneyViewClient = MoneyViewClient(getChannel());
+  final channel = getChannel();
+
+  late final AuthClient authClient = AuthClient(channel);
+
+  // every call except the login carries the session token
+  late final dynamic moneyViewClient = MoneyViewClient(channel,
+      interceptors: [BearerTokenInterceptor(() => token)]);
+
+  String? token;
+
+  Future<void> login(String userName, String password) async {
+    var response = await authClient
+        .login(LoginRequest(userName: userName, password: password));
+    token = response.token;
+    notifyListeners();
+  }
+
+  Future<void> logout() async {
+    try {
+      await moneyViewClient.logout(Empty());
+    } finally {
+      token = null;
+      notifyListeners();
+    }
+  }
 
   void getNext() {
     current = WordPair.random();
@@ -25,3 +50,34 @@ class ApplicationState extends ChangeNotifier {
     notifyListeners();
   }
 }
+
+/// Adds the session token as `authorization: Bearer <token>` to every call.
+class BearerTokenInterceptor extends ClientInterceptor {
+  final String? Function() token;
+
+  BearerTokenInterceptor(this.token);
+
+  CallOptions _withToken(CallOptions options) {
+    var current = token();
+    if (current == null) {
+      return options;
+    }
+    return options
+        .mergedWith(CallOptions(metadata: {'authorization': 'Bearer $current'}));
+  }
+
+  @override
+  ResponseFuture<R> interceptUnary<Q, R>(ClientMethod<Q, R> method, Q request,
+      CallOptions options, ClientUnaryInvoker<Q, R> invoker) {
+    return invoker(method, request, _withToken(options));
+  }
+
+  @override
+  ResponseStream<R> interceptStreaming<Q, R>(
+      ClientMethod<Q, R> method,
+      Stream<Q> requests,
+      CallOptions options,
+      ClientStreamingInvoker<Q, R> invoker) {
+    return invoker(method, requests, _withToken(options));
+  }
+}
@@ -1,8 +1,10 @@
+import 'package:app/application_state.dart';
 import 'package:app/ui/partner_balance.dart';
 import 'package:app/ui/tag_balance.dart';
 import 'package:app/ui/tag_manager.dart';
 import 'package:app/ui/transaction_list.dart';
 import 'package:flutter/material.dart';
+import 'package:provider/provider.dart';
 
 class IndexPage extends StatefulWidget {
   @override
@@ -65,6 +67,11 @@ class _IndexPageState extends State<IndexPage> {
           label: Text('Tag Manager'),
         ),
       ],
+      trailing: IconButton(
+        icon: Icon(Icons.logout),
+        tooltip: 'Abmelden',
+        onPressed: () => context.read<ApplicationState>().logout(),
+      ),
       selectedIndex: selectedIndex,
       onDestinationSelected: (value) {
         setState(() {
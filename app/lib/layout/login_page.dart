@@ -0,0 +1,81 @@
+import 'package:app/application_state.dart';
+import 'package:flutter/material.dart';
+import 'package:grpc/grpc.dart';
+import 'package:provider/provider.dart';
+
+class LoginPage extends StatefulWidget {
+  @override
+  State<LoginPage> createState() => _LoginPageState();
+}
+
+class _LoginPageState extends State<LoginPage> {
+  final userNameController = TextEditingController();
+  final passwordController = TextEditingController();
+  String? error;
+  bool loading = false;
+
+  Future<void> _login(BuildContext context) async {
+    setState(() {
+      loading = true;
+      error = null;
+    });
+    try {
+      await context
+          .read<ApplicationState>()
+          .login(userNameController.text, passwordController.text);
+    } on GrpcError catch (e) {
+      setState(() {
+        error = e.code == StatusCode.unauthenticated
+            ? 'Benutzername oder Passwort falsch'
+            : 'Anmeldung fehlgeschlagen: ${e.message}';
+      });
+    } finally {
+      if (mounted) {
+        setState(() {
+          loading = false;
+        });
+      }
+    }
+  }
+
+  @override
+  void dispose() {
+    userNameController.dispose();
+    passwordController.dispose();
+    super.dispose();
+  }
+
+  @override
+  Widget build(BuildContext context) {
+    return Scaffold(
+      body: Center(
+        child: ConstrainedBox(
+          constraints: BoxConstraints(maxWidth: 360),
+          child: Column(
+            mainAxisSize: MainAxisSize.min,
+            children: [
+              TextField(
+                controller: userNameController,
+                decoration: InputDecoration(labelText: 'Benutzername'),
+              ),
+              TextField(
+                controller: passwordController,
+                obscureText: true,
+                decoration: InputDecoration(labelText: 'Passwort'),
+                onSubmitted: (_) => _login(context),
+              ),
+              SizedBox(height: 16),
+              if (error != null)
+                Text(error!,
+                    style: TextStyle(color: Theme.of(context).colorScheme.error)),
+              ElevatedButton(
+                onPressed: loading ? null : () => _login(context),
+                child: Text('Anmelden'),
+              ),
+            ],
+          ),
+        ),
+      ),
+    );
+  }
+}
@@ -1,5 +1,6 @@
 import 'package:app/application_state.dart';
 import 'package:app/layout/index_page.dart';
+import 'package:app/layout/login_page.dart';
 import 'package:flutter/material.dart';
 import 'package:provider/provider.dart';
 
@@ -17,7 +18,10 @@ class MoneyView extends StatelessWidget {
           useMaterial3: true,
           colorScheme: ColorScheme.fromSeed(seedColor: Colors.blue),
         ),
-        home: IndexPage(),
+        home: Consumer<ApplicationState>(
+          builder: (context, state, child) =>
+              state.token == null ? LoginPage() : IndexPage(),
+        ),
       ),
     );
   }
@@ -26,7 +26,7 @@ class _PartnerBalanceWidgetState extends State<PartnerBalanceWidget> {
 
     context.watch<ApplicationState>().moneyViewClient.getTags(Empty());
     if (isEmpty) {
-      appState.moneyViewClient.getPartnerBalance(Empty()).then((response) {
+      appState.moneyViewClient.getPartnerBalance(BalanceRequest()).then((response) {
         setState(() {
           partnerExpenses = response.expenses;
           partnerExpenses
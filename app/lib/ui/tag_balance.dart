@@ -24,7 +24,7 @@ class _TagBalanceWidgetState extends State<TagBalanceWidget> {
   Widget build(BuildContext context) {
     var appState = context.watch<ApplicationState>();
     if (isEmpty) {
-      appState.moneyViewClient.getTagBalance(Empty()).then((response) {
+      appState.moneyViewClient.getTagBalance(BalanceRequest()).then((response) {
         setState(() {
           partnerExpenses = response.expenses;
           partnerExpenses
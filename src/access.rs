@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use crate::api::AccountRole;
+use crate::database::AccountGrant;
+use crate::error::{Error, Result};
+use crate::logging::sensitive;
+
+/// Accounts a user may access and the role on each of them.
+///
+/// Every query on account data is restricted to [`Access::readable_accounts`], writes to
+/// [`Access::editable_accounts`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Access {
+    pub(crate) user_name: String,
+    roles: HashMap<String, AccountRole>,
+}
+
+impl Access {
+    pub(crate) fn new(user_name: String, grants: Vec<AccountGrant>) -> Self {
+        let roles = grants
+            .into_iter()
+            .filter(|grant| grant.user_name == user_name)
+            .map(|grant| (grant.account_id.clone(), grant.role()))
+            .collect();
+        Self { user_name, roles }
+    }
+
+    pub(crate) fn role(&self, account_id: &str) -> Option<AccountRole> {
+        self.roles.get(account_id).copied()
+    }
+
+    /// Whether the user has at least `role` on the account.
+    pub(crate) fn can(&self, account_id: &str, role: AccountRole) -> bool {
+        self.role(account_id)
+            .is_some_and(|granted| granted as i32 >= role as i32)
+    }
+
+    pub(crate) fn require(&self, account_id: &str, role: AccountRole) -> Result<()> {
+        if self.can(account_id, role) {
+            Ok(())
+        } else {
+            // the message ends up in error logs, account ids are IBANs
+            Err(Error::PermissionDenied(format!(
+                "{} role required on account {}",
+                role.as_str_name(),
+                sensitive(account_id)
+            )))
+        }
+    }
+
+    pub(crate) fn readable_accounts(&self) -> Vec<String> {
+        self.accounts(AccountRole::Viewer)
+    }
+
+    pub(crate) fn editable_accounts(&self) -> Vec<String> {
+        self.accounts(AccountRole::Editor)
+    }
+
+    fn accounts(&self, role: AccountRole) -> Vec<String> {
+        let mut accounts: Vec<String> = self
+            .roles
+            .keys()
+            .filter(|account_id| self.can(account_id, role))
+            .cloned()
+            .collect();
+        accounts.sort();
+        accounts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(account_id: &str, user_name: &str, role: AccountRole) -> AccountGrant {
+        AccountGrant::new(account_id.to_string(), user_name.to_string(), role)
+    }
+
+    #[test]
+    fn test_access() {
+        let access = Access::new(
+            "anna".to_string(),
+            vec![
+                grant("giro", "anna", AccountRole::Owner),
+                grant("savings", "anna", AccountRole::Viewer),
+                grant("other", "ben", AccountRole::Owner),
+            ],
+        );
+        assert_eq!(access.readable_accounts(), vec!["giro", "savings"]);
+        assert_eq!(access.editable_accounts(), vec!["giro"]);
+        assert!(access.require("giro", AccountRole::Owner).is_ok());
+        assert!(access.require("savings", AccountRole::Editor).is_err());
+        assert!(access.require("other", AccountRole::Viewer).is_err());
+    }
+}
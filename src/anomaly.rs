@@ -0,0 +1,214 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+use surrealdb::sql::Thing;
+
+use crate::api::AnomalyKind;
+use crate::database::TransactionRecord;
+use crate::recurring::median;
+
+/// Minimum number of earlier transactions before amounts are compared to the history.
+const MIN_PARTNER_HISTORY: usize = 3;
+const MIN_TAG_HISTORY: usize = 10;
+/// Factor above the median amount that counts as unusual.
+const PARTNER_DEVIATION: f32 = 2.0;
+const TAG_DEVIATION: f32 = 5.0;
+/// Deviations below this absolute amount are ignored.
+const MIN_DEVIATION_AMOUNT: f32 = 10.0;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AnomalySettings {
+    pub(crate) duplicate_window_days: i64,
+    pub(crate) new_partner_threshold: f32, // 0 disables the check
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Finding {
+    pub(crate) transaction: TransactionRecord,
+    pub(crate) kind: AnomalyKind,
+    pub(crate) reason: String,
+}
+
+/// Flags duplicate debits, amounts far above a partner's or tag's history and
+/// large payments to partners never seen before.
+pub(crate) fn detect(
+    transactions: &[TransactionRecord],
+    settings: AnomalySettings,
+) -> Vec<Finding> {
+    let mut by_partner: HashMap<&str, Vec<&TransactionRecord>> = HashMap::new();
+    for transaction in transactions {
+        by_partner
+            .entry(transaction.partner_key())
+            .or_default()
+            .push(transaction);
+    }
+    let mut findings: Vec<Finding> = by_partner
+        .into_par_iter()
+        .flat_map_iter(|(_, mut series)| {
+            series.sort_by_key(|t| t.date);
+            partner_findings(&series, settings)
+        })
+        .collect();
+    findings.extend(tag_findings(transactions));
+    findings.sort_by_key(|f| Reverse(f.transaction.date));
+    findings
+}
+
+fn partner_findings(series: &[&TransactionRecord], settings: AnomalySettings) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for (index, transaction) in series.iter().enumerate() {
+        let earlier = &series[..index];
+
+        if let Some(duplicate) = earlier.iter().rev().find(|other| {
+            (transaction.date - other.date).num_days() <= settings.duplicate_window_days
+                && other.account_id == transaction.account_id
+                && other.total_amount == transaction.total_amount
+        }) {
+            findings.push(finding(
+                transaction,
+                AnomalyKind::Duplicate,
+                format!(
+                    "same amount {:.2} as booking of {}",
+                    transaction.total_amount, duplicate.date
+                ),
+            ));
+        }
+
+        if index == 0
+            && settings.new_partner_threshold > 0.0
+            && transaction.total_amount.abs() >= settings.new_partner_threshold
+        {
+            findings.push(finding(
+                transaction,
+                AnomalyKind::NewPartner,
+                format!(
+                    "first booking with {} over {:.2}",
+                    transaction.partner_name, transaction.total_amount
+                ),
+            ));
+        }
+
+        let history: Vec<f32> = earlier
+            .iter()
+            .filter(|other| (other.total_amount < 0.0) == (transaction.total_amount < 0.0))
+            .map(|other| other.total_amount.abs())
+            .collect();
+        if history.len() >= MIN_PARTNER_HISTORY {
+            let usual = median(history);
+            let amount = transaction.total_amount.abs();
+            if amount >= usual * PARTNER_DEVIATION && amount - usual >= MIN_DEVIATION_AMOUNT {
+                findings.push(finding(
+                    transaction,
+                    AnomalyKind::UnusualPartnerAmount,
+                    format!(
+                        "{:.2} is {:.1} times the usual {:.2} for {}",
+                        amount,
+                        amount / usual,
+                        usual,
+                        transaction.partner_name
+                    ),
+                ));
+            }
+        }
+    }
+    findings
+}
+
+fn tag_findings(transactions: &[TransactionRecord]) -> Vec<Finding> {
+    let mut by_tag: HashMap<&Thing, Vec<f32>> = HashMap::new();
+    for transaction in transactions {
+        for item in transaction
+            .line_items
+            .iter()
+            .filter(|item| item.amount < 0.0)
+        {
+            by_tag.entry(&item.tag_id).or_default().push(-item.amount);
+        }
+    }
+    let usual: HashMap<&Thing, f32> = by_tag
+        .into_iter()
+        .filter(|(_, amounts)| amounts.len() >= MIN_TAG_HISTORY)
+        .map(|(tag, amounts)| (tag, median(amounts)))
+        .collect();
+
+    transactions
+        .par_iter()
+        .filter_map(|transaction| {
+            transaction.line_items.iter().find_map(|item| {
+                let usual = *usual.get(&item.tag_id)?;
+                let amount = -item.amount;
+                if amount < usual * TAG_DEVIATION || amount - usual < MIN_DEVIATION_AMOUNT {
+                    return None;
+                }
+                Some(finding(
+                    transaction,
+                    AnomalyKind::UnusualTagAmount,
+                    format!(
+                        "{:.2} is {:.1} times the usual {:.2} for tag {}",
+                        amount,
+                        amount / usual,
+                        usual,
+                        item.tag_id.id.to_raw()
+                    ),
+                ))
+            })
+        })
+        .collect()
+}
+
+fn finding(transaction: &TransactionRecord, kind: AnomalyKind, reason: String) -> Finding {
+    Finding {
+        transaction: transaction.clone(),
+        kind,
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn booking(id: &str, day: u32, partner: &str, amount: f32) -> TransactionRecord {
+        TransactionRecord {
+            id: Thing::from(("transaction", id)),
+            account_id: "15091704/3000185000".to_string(),
+            date: NaiveDate::from_ymd_opt(2024, 7, day).unwrap(),
+            partner_name: partner.to_string(),
+            total_amount: amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        let transactions = vec![
+            booking("a", 1, "Stadtwerke", -60.0),
+            booking("b", 2, "Stadtwerke", -62.0),
+            booking("c", 3, "Stadtwerke", -61.0),
+            booking("d", 10, "Stadtwerke", -130.0),
+            booking("e", 12, "ALDI", -30.84),
+            booking("f", 13, "ALDI", -30.84),
+            booking("g", 14, "Unbekannt", -500.0),
+        ];
+        let settings = AnomalySettings {
+            duplicate_window_days: 3,
+            new_partner_threshold: 200.0,
+        };
+
+        let findings = detect(&transactions, settings);
+        let kinds: Vec<(String, AnomalyKind)> = findings
+            .iter()
+            .map(|f| (f.transaction.id.id.to_raw(), f.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("g".to_string(), AnomalyKind::NewPartner),
+                ("f".to_string(), AnomalyKind::Duplicate),
+                ("d".to_string(), AnomalyKind::UnusualPartnerAmount),
+            ]
+        );
+    }
+}
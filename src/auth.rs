@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::database::UserRecord;
+use crate::error::{Error, Result};
+
+pub(crate) const MIN_PASSWORD_LENGTH: usize = 10;
+const BEARER_PREFIX: &str = "Bearer ";
+
+lazy_static! {
+    /// Hash of a random password, checked for unknown users so that their logins take as
+    /// long as wrong passwords.
+    static ref DUMMY_HASH: String = Argon2::default()
+        .hash_password(
+            uuid::Uuid::new_v4().as_bytes(),
+            &SaltString::generate(&mut OsRng)
+        )
+        .map(|hash| hash.to_string())
+        .unwrap_or_default();
+}
+
+/// Content of a session token.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct Claims {
+    pub(crate) sub: String, // User name
+    pub(crate) admin: bool,
+    pub(crate) jti: String, // Token id, used for revocation
+    pub(crate) iat: i64, // Milliseconds, so that tokens issued right after a revocation stay valid
+    pub(crate) exp: i64,
+}
+
+/// Issues and checks signed session tokens (HS256 JWT) and keeps the ids of revoked tokens
+/// as well as the time from which on the sessions of a user are valid.
+///
+/// Used as interceptor, it rejects every request without a valid token and stores the
+/// [`Claims`] in the request extensions.
+#[derive(Clone)]
+pub(crate) struct Sessions {
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+    lifetime: chrono::Duration,
+    revoked: Arc<RwLock<HashSet<String>>>,
+    sessions_since: Arc<RwLock<HashMap<String, i64>>>, // User name to Unix timestamp in milliseconds
+}
+
+impl std::fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sessions")
+            .field("lifetime", &self.lifetime)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sessions {
+    pub(crate) fn new(
+        secret: &[u8],
+        lifetime: chrono::Duration,
+        revoked: Vec<String>,
+        sessions_since: HashMap<String, i64>,
+    ) -> Self {
+        Self {
+            encoding_key: EncodingKey::from_secret(secret),
+            decoding_key: DecodingKey::from_secret(secret),
+            lifetime,
+            revoked: Arc::new(RwLock::new(revoked.into_iter().collect())),
+            sessions_since: Arc::new(RwLock::new(sessions_since)),
+        }
+    }
+
+    pub(crate) fn issue(&self, user: &UserRecord) -> Result<(String, Claims)> {
+        let now = chrono::Utc::now();
+        let claims = Claims {
+            sub: user.user_name.clone(),
+            admin: user.is_admin,
+            jti: uuid::Uuid::new_v4().to_string(),
+            iat: now.timestamp_millis(),
+            exp: (now + self.lifetime).timestamp(),
+        };
+        let token = encode(&Header::default(), &claims, &self.encoding_key)
+            .map_err(|e| Error::Internal(e.to_string()))?;
+        Ok((token, claims))
+    }
+
+    pub(crate) fn verify(&self, token: &str) -> Result<Claims> {
+        let claims = decode::<Claims>(token, &self.decoding_key, &Validation::default())
+            .map_err(|_| Error::Unauthenticated("invalid or expired token".to_string()))?
+            .claims;
+        if self.is_revoked(&claims) {
+            return Err(Error::Unauthenticated("token has been revoked".to_string()));
+        }
+        Ok(claims)
+    }
+
+    pub(crate) fn revoke(&self, jti: &str) {
+        if let Ok(mut revoked) = self.revoked.write() {
+            revoked.insert(jti.to_string());
+        }
+    }
+
+    /// Revokes all tokens of the user issued before `since`, in milliseconds.
+    pub(crate) fn revoke_user(&self, user_name: &str, since: i64) {
+        if let Ok(mut sessions_since) = self.sessions_since.write() {
+            sessions_since.insert(user_name.to_string(), since);
+        }
+    }
+
+    fn is_revoked(&self, claims: &Claims) -> bool {
+        // a poisoned lock fails closed
+        let revoked = self
+            .revoked
+            .read()
+            .map(|revoked| revoked.contains(&claims.jti))
+            .unwrap_or(true);
+        let expired = self
+            .sessions_since
+            .read()
+            .map(|sessions_since| {
+                sessions_since
+                    .get(&claims.sub)
+                    .is_some_and(|since| claims.iat < *since)
+            })
+            .unwrap_or(true);
+        revoked || expired
+    }
+}
+
+impl Interceptor for Sessions {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER_PREFIX))
+            .ok_or_else(|| Status::unauthenticated("missing bearer token"))?;
+        let claims = self.verify(token)?;
+        request.extensions_mut().insert(claims);
+        Ok(request)
+    }
+}
+
+/// Claims of the authenticated caller, set by the [`Sessions`] interceptor.
+pub(crate) fn claims<T>(request: &Request<T>) -> Result<Claims> {
+    request
+        .extensions()
+        .get::<Claims>()
+        .cloned()
+        .ok_or_else(|| Error::Unauthenticated("missing session".to_string()))
+}
+
+/// Hashes a password with argon2id and a random salt into a PHC string.
+pub(crate) async fn hash_password(password: String) -> Result<String, Status> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(Status::invalid_argument(format!(
+            "password needs at least {} characters",
+            MIN_PASSWORD_LENGTH
+        )));
+    }
+    let (send, recv) = tokio::sync::oneshot::channel();
+    rayon::spawn(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| Status::internal(e.to_string()));
+        let _ = send.send(hash);
+    });
+    recv.await.map_err(|e| Status::internal(e.to_string()))?
+}
+
+pub(crate) async fn verify_password(password: String, hash: String) -> bool {
+    let (send, recv) = tokio::sync::oneshot::channel();
+    rayon::spawn(move || {
+        let _ = send.send(verify(&password, &hash));
+    });
+    recv.await.unwrap_or(false)
+}
+
+/// Takes as long as [`verify_password`] with a wrong password, used for unknown users.
+pub(crate) async fn reject_password(password: String) {
+    let (send, recv) = tokio::sync::oneshot::channel();
+    rayon::spawn(move || {
+        let _ = send.send(verify(&password, &DUMMY_HASH));
+    });
+    let _ = recv.await;
+}
+
+fn verify(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use surrealdb::sql::Thing;
+
+    #[tokio::test]
+    async fn test_sessions() {
+        let user = UserRecord {
+            id: Thing::from(("user", "anna")),
+            user_name: "anna".to_string(),
+            password_hash: hash_password("correct horse".to_string()).await.unwrap(),
+            is_admin: true,
+            sessions_since: 0,
+        };
+        assert!(verify_password("correct horse".to_string(), user.password_hash.clone()).await);
+        assert!(!verify_password("wrong horse".to_string(), user.password_hash.clone()).await);
+        assert!(hash_password("short".to_string()).await.is_err());
+
+        let mut sessions = Sessions::new(
+            b"secret",
+            chrono::Duration::hours(1),
+            Vec::new(),
+            HashMap::new(),
+        );
+        let (token, claims) = sessions.issue(&user).unwrap();
+        let mut request = Request::new(());
+        request.metadata_mut().insert(
+            "authorization",
+            format!("{}{}", BEARER_PREFIX, token).parse().unwrap(),
+        );
+        let request = sessions.call(request).unwrap();
+        assert_eq!(super::claims(&request).unwrap(), claims);
+
+        sessions.revoke(&claims.jti);
+        assert!(sessions.verify(&token).is_err());
+        assert!(sessions.call(Request::new(())).is_err());
+
+        let (token, claims) = sessions.issue(&user).unwrap();
+        sessions.revoke_user("bert", claims.iat + 1);
+        assert!(sessions.verify(&token).is_ok());
+        sessions.revoke_user("anna", claims.iat + 1);
+        assert!(sessions.verify(&token).is_err());
+
+        // a password change revokes the old tokens, the new ones are valid right away
+        sessions.revoke_user("anna", chrono::Utc::now().timestamp_millis());
+        let (token, _) = sessions.issue(&user).unwrap();
+        assert!(sessions.verify(&token).is_ok());
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{Duration, NaiveDate};
+
+use crate::api::{self, AccountBalanceHistory, AccountKind, BalanceMismatch, BalancePoint};
+use crate::database::{date_to_days, ManualAccount, StatementRecord, TransactionRecord};
+use crate::error::Result;
+use crate::exchange::RateTable;
+
+/// Balances that differ by less than this are considered equal.
+const BALANCE_TOLERANCE: f32 = 0.01;
+
+/// End-of-day balance per account, taken from the last booking of each day.
+pub(crate) fn end_of_day_balances(
+    transactions: &[TransactionRecord],
+) -> HashMap<String, BTreeMap<NaiveDate, f32>> {
+    let mut last_bookings: HashMap<(&str, NaiveDate), &TransactionRecord> = HashMap::new();
+    for transaction in transactions {
+        let last = last_bookings
+            .entry((transaction.account_id.as_str(), transaction.date))
+            .or_insert(transaction);
+        if transaction.position > last.position {
+            *last = transaction;
+        }
+    }
+
+    let mut balances: HashMap<String, BTreeMap<NaiveDate, f32>> = HashMap::new();
+    for ((account_id, date), transaction) in last_bookings {
+        balances
+            .entry(account_id.to_string())
+            .or_default()
+            .insert(date, transaction.balance_after_transaction);
+    }
+    balances
+}
+
+/// Builds the balance series of each account within `from..=to` and compares the stored
+/// balances with the closing balances of the imported statements.
+pub(crate) fn account_histories(
+    balances: &HashMap<String, BTreeMap<NaiveDate, f32>>,
+    currencies: &HashMap<String, String>,
+    statements: &[StatementRecord],
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<AccountBalanceHistory> {
+    let mut histories: Vec<AccountBalanceHistory> = balances
+        .iter()
+        .map(|(account_id, series)| {
+            let mismatches = statements
+                .iter()
+                .filter(|statement| &statement.account_id == account_id)
+                .filter_map(|statement| {
+                    let stored = balance_on(series, statement.closing_date)?;
+                    ((stored - statement.closing_balance).abs() >= BALANCE_TOLERANCE).then(|| {
+                        BalanceMismatch {
+                            date: date_to_days(statement.closing_date),
+                            statement_balance: statement.closing_balance,
+                            stored_balance: stored,
+                        }
+                    })
+                })
+                .collect();
+            AccountBalanceHistory {
+                account_id: account_id.clone(),
+                points: series
+                    .range(from..=to)
+                    .map(|(date, balance)| BalancePoint {
+                        date: date_to_days(*date),
+                        balance: *balance,
+                    })
+                    .collect(),
+                current_balance: balance_on(series, to).unwrap_or_default(),
+                mismatches,
+                currency: currencies.get(account_id).cloned().unwrap_or_default(),
+            }
+        })
+        .collect();
+    histories.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+    histories
+}
+
+/// Converts the balance series of every account from its own currency into `currency`
+/// with the rate of each day.
+pub(crate) fn convert_balances(
+    balances: &HashMap<String, BTreeMap<NaiveDate, f32>>,
+    currencies: &HashMap<String, String>,
+    rates: &RateTable,
+    currency: &str,
+) -> Result<HashMap<String, BTreeMap<NaiveDate, f32>>> {
+    balances
+        .iter()
+        .map(|(account_id, series)| {
+            let from = currencies
+                .get(account_id)
+                .map(String::as_str)
+                .unwrap_or_default();
+            let series = series
+                .iter()
+                .map(|(date, balance)| Ok((*date, rates.convert(*balance, from, currency, *date)?)))
+                .collect::<Result<_>>()?;
+            Ok((account_id.clone(), series))
+        })
+        .collect()
+}
+
+/// Daily sum of all account balances and manual assets minus manual liabilities.
+pub(crate) fn net_worth(
+    balances: &HashMap<String, BTreeMap<NaiveDate, f32>>,
+    manual_accounts: &[ManualAccount],
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Vec<BalancePoint> {
+    let mut points = Vec::new();
+    let mut date = from;
+    while date <= to {
+        let accounts: f32 = balances
+            .values()
+            .filter_map(|series| balance_on(series, date))
+            .sum();
+        let manual: f32 = manual_accounts
+            .iter()
+            .filter(|account| account.date <= date)
+            .map(|account| match account.kind() {
+                AccountKind::Asset => account.balance,
+                AccountKind::Liability => -account.balance,
+            })
+            .sum();
+        points.push(BalancePoint {
+            date: date_to_days(date),
+            balance: accounts + manual,
+        });
+        date += Duration::days(1);
+    }
+    points
+}
+
+impl ManualAccount {
+    pub(crate) fn kind(&self) -> AccountKind {
+        AccountKind::try_from(self.kind).unwrap_or(AccountKind::Asset)
+    }
+}
+
+impl From<ManualAccount> for api::ManualAccount {
+    fn from(value: ManualAccount) -> Self {
+        api::ManualAccount {
+            id: value.id.id.to_raw(),
+            name: value.name,
+            kind: value.kind,
+            balance: value.balance,
+            date: date_to_days(value.date),
+            currency: value.currency,
+        }
+    }
+}
+
+/// Balance at the end of `date`, carried forward from the last booking before it.
+fn balance_on(series: &BTreeMap<NaiveDate, f32>, date: NaiveDate) -> Option<f32> {
+    series
+        .range(..=date)
+        .next_back()
+        .map(|(_, balance)| *balance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use surrealdb::sql::Thing;
+
+    fn date(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 7, d).unwrap()
+    }
+
+    fn booking(day: u32, position: u32, balance: f32) -> TransactionRecord {
+        TransactionRecord {
+            account_id: "15091704/3000185000".to_string(),
+            date: date(day),
+            position,
+            balance_after_transaction: balance,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_balances() {
+        let transactions = vec![
+            booking(16, 1, -862.5),
+            booking(16, 0, -758.0),
+            booking(18, 0, -900.0),
+        ];
+        let statement = StatementRecord {
+            id: Thing::from(("statement", "15091704/3000185000-2024-07-16")),
+            account_id: "15091704/3000185000".to_string(),
+            opening_date: date(16),
+            opening_balance: -653.5,
+            closing_date: date(16),
+            closing_balance: -975.5,
+            currency: "EUR".to_string(),
+        };
+        let savings = ManualAccount {
+            id: Thing::from(("manual_account", "savings")),
+            name: "Tagesgeld".to_string(),
+            kind: AccountKind::Asset as i32,
+            balance: 1000.0,
+            date: date(17),
+            currency: String::new(),
+            user_name: "anna".to_string(),
+        };
+
+        let balances = end_of_day_balances(&transactions);
+        let currencies = HashMap::new();
+        let histories = account_histories(&balances, &currencies, &[statement], date(1), date(31));
+        assert_eq!(histories[0].points.len(), 2);
+        assert_eq!(histories[0].points[0].balance, -862.5);
+        assert_eq!(histories[0].current_balance, -900.0);
+        assert_eq!(histories[0].mismatches.len(), 1);
+
+        let net_worth = net_worth(&balances, &[savings], date(16), date(18));
+        let values: Vec<f32> = net_worth.iter().map(|p| p.balance).collect();
+        assert_eq!(values, vec![-862.5, 137.5, 100.0]);
+    }
+}
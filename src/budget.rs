@@ -0,0 +1,144 @@
+use chrono::{Datelike, Duration, Months, NaiveDate};
+
+use crate::api::{self, BudgetStatus, CashFlowPeriod, CashFlowPoint};
+use crate::database::{date_to_days, days_to_date, Budget};
+
+/// Returns the first day of the period containing `date` and the first day of the next one.
+pub(crate) fn period_bounds(date: NaiveDate, period: CashFlowPeriod) -> (NaiveDate, NaiveDate) {
+    let first_of_month =
+        |month: u32| NaiveDate::from_ymd_opt(date.year(), month, 1).unwrap_or(date);
+    match period {
+        CashFlowPeriod::Day => (date, date + Duration::days(1)),
+        CashFlowPeriod::Week => {
+            let start = date - Duration::days(date.weekday().num_days_from_monday() as i64);
+            (start, start + Duration::days(7))
+        }
+        CashFlowPeriod::Month => {
+            let start = first_of_month(date.month());
+            (start, start + Months::new(1))
+        }
+        CashFlowPeriod::Quarter => {
+            let start = first_of_month(date.month0() / 3 * 3 + 1);
+            (start, start + Months::new(3))
+        }
+        CashFlowPeriod::Year => {
+            let start = first_of_month(1);
+            (start, start + Months::new(12))
+        }
+    }
+}
+
+/// Computes the state of `budget` on `date` from the net cash flow of its tag per period.
+pub(crate) fn status(budget: &Budget, series: &[CashFlowPoint], date: NaiveDate) -> BudgetStatus {
+    let period = budget.period();
+    let (period_start, period_end) = period_bounds(date, period);
+    let spent_in = |start: NaiveDate| -> f32 {
+        series
+            .iter()
+            .filter(|point| days_to_date(point.period_start) == start)
+            .map(|point| -point.net)
+            .sum()
+    };
+
+    let mut carried_over = 0.0;
+    if budget.rollover {
+        let mut start = period_bounds(budget.start_date, period).0;
+        while start < period_start {
+            carried_over = (budget.amount + carried_over - spent_in(start)).max(0.0);
+            start = period_bounds(start, period).1;
+        }
+    }
+
+    let available = budget.amount + carried_over;
+    let spent = if date < budget.start_date {
+        0.0
+    } else {
+        spent_in(period_start)
+    };
+    let elapsed = (date - period_start).num_days() + 1;
+    let length = (period_end - period_start).num_days();
+    BudgetStatus {
+        budget: Some(budget.clone().into()),
+        period_start: date_to_days(period_start),
+        period_end: date_to_days(period_end - Duration::days(1)),
+        carried_over,
+        available,
+        spent,
+        remaining: available - spent,
+        projected: spent / elapsed as f32 * length as f32,
+    }
+}
+
+impl Budget {
+    pub(crate) fn period(&self) -> CashFlowPeriod {
+        CashFlowPeriod::try_from(self.period).unwrap_or(CashFlowPeriod::Month)
+    }
+}
+
+impl From<Budget> for api::Budget {
+    fn from(value: Budget) -> Self {
+        api::Budget {
+            id: value.id.id.to_raw(),
+            tag_id: value.tag_id.id.to_raw(),
+            period: value.period,
+            amount: value.amount,
+            rollover: value.rollover,
+            start_date: date_to_days(value.start_date),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use surrealdb::sql::Thing;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    fn point(start: NaiveDate, net: f32) -> CashFlowPoint {
+        CashFlowPoint {
+            period_start: date_to_days(start),
+            net,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_period_bounds() {
+        assert_eq!(
+            period_bounds(date(2024, 7, 17), CashFlowPeriod::Week),
+            (date(2024, 7, 15), date(2024, 7, 22))
+        );
+        assert_eq!(
+            period_bounds(date(2024, 8, 31), CashFlowPeriod::Quarter),
+            (date(2024, 7, 1), date(2024, 10, 1))
+        );
+    }
+
+    #[test]
+    fn test_rollover() {
+        let budget = Budget {
+            id: Thing::from(("budget", "groceries")),
+            tag_id: Thing::from(("tag", "groceries")),
+            period: CashFlowPeriod::Month as i32,
+            amount: 100.0,
+            rollover: true,
+            start_date: date(2024, 5, 1),
+            user_name: "anna".to_string(),
+        };
+        let series = vec![
+            point(date(2024, 5, 1), -80.0),
+            point(date(2024, 6, 1), -130.0),
+            point(date(2024, 7, 1), -50.0),
+        ];
+
+        let status = status(&budget, &series, date(2024, 7, 15));
+        // may leaves 20, june overspends the 120 available, so nothing is carried into july
+        assert_eq!(status.carried_over, 0.0);
+        assert_eq!(status.spent, 50.0);
+        assert_eq!(status.remaining, 50.0);
+        assert_eq!(status.period_end, date_to_days(date(2024, 7, 31)));
+    }
+}
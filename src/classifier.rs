@@ -0,0 +1,164 @@
+use std::collections::{HashMap, HashSet};
+
+use rayon::prelude::*;
+use surrealdb::sql::Thing;
+
+use crate::database::TransactionRecord;
+
+/// Multinomial naive Bayes classifier proposing a tag for a transaction
+/// based on partner name, description tokens, partner id and amount bucket.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct TagClassifier {
+    tag_counts: HashMap<Thing, usize>,
+    feature_counts: HashMap<Thing, HashMap<String, usize>>,
+    feature_totals: HashMap<Thing, usize>,
+    vocabulary: HashSet<String>,
+    samples: usize,
+}
+
+impl TagClassifier {
+    pub(crate) fn train(samples: Vec<(Vec<String>, Thing)>) -> Self {
+        samples
+            .into_par_iter()
+            .fold(TagClassifier::default, |mut classifier, (features, tag)| {
+                classifier.add_sample(features, tag);
+                classifier
+            })
+            .reduce(TagClassifier::default, TagClassifier::merge)
+    }
+
+    fn add_sample(&mut self, features: Vec<String>, tag: Thing) {
+        self.samples += 1;
+        *self.tag_counts.entry(tag.clone()).or_default() += 1;
+        *self.feature_totals.entry(tag.clone()).or_default() += features.len();
+        let counts = self.feature_counts.entry(tag).or_default();
+        for feature in features {
+            *counts.entry(feature.clone()).or_default() += 1;
+            self.vocabulary.insert(feature);
+        }
+    }
+
+    fn merge(mut self, other: Self) -> Self {
+        self.samples += other.samples;
+        for (tag, count) in other.tag_counts {
+            *self.tag_counts.entry(tag).or_default() += count;
+        }
+        for (tag, count) in other.feature_totals {
+            *self.feature_totals.entry(tag).or_default() += count;
+        }
+        for (tag, features) in other.feature_counts {
+            let counts = self.feature_counts.entry(tag).or_default();
+            for (feature, count) in features {
+                *counts.entry(feature).or_default() += count;
+            }
+        }
+        self.vocabulary.extend(other.vocabulary);
+        self
+    }
+
+    /// Returns the most probable tag together with its posterior probability.
+    pub(crate) fn predict(&self, features: &[String]) -> Option<(Thing, f32)> {
+        if self.samples == 0 {
+            return None;
+        }
+        let vocabulary = self.vocabulary.len() as f64;
+        let scores: Vec<(Thing, f64)> = self
+            .tag_counts
+            .iter()
+            .map(|(tag, count)| {
+                let prior = (*count as f64 / self.samples as f64).ln();
+                let total = *self.feature_totals.get(tag).unwrap_or(&0) as f64;
+                let counts = self.feature_counts.get(tag);
+                let likelihood: f64 = features
+                    .iter()
+                    .map(|feature| {
+                        let count = counts
+                            .and_then(|counts| counts.get(feature))
+                            .copied()
+                            .unwrap_or_default() as f64;
+                        ((count + 1.0) / (total + vocabulary)).ln()
+                    })
+                    .sum();
+                (tag.clone(), prior + likelihood)
+            })
+            .collect();
+
+        let max = scores
+            .iter()
+            .map(|(_, score)| *score)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let norm: f64 = scores.iter().map(|(_, score)| (score - max).exp()).sum();
+        scores
+            .into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(tag, score)| (tag, ((score - max).exp() / norm) as f32))
+    }
+}
+
+/// Extracts the classifier features of a transaction.
+pub(crate) fn features(transaction: &TransactionRecord) -> Vec<String> {
+    let mut features: Vec<String> = Vec::new();
+    features.extend(tokens(&transaction.partner_name).map(|t| format!("p:{}", t)));
+    features.extend(tokens(&transaction.description).map(|t| format!("d:{}", t)));
+    if !transaction.partner_id.is_empty() {
+        features.push(format!("i:{}", transaction.partner_id));
+    }
+    features.push(format!("a:{}", amount_bucket(transaction.total_amount)));
+    features
+}
+
+fn tokens(input: &str) -> impl Iterator<Item = String> + '_ {
+    input
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| token.chars().count() >= 3 && !token.chars().all(|c| c.is_numeric()))
+        .map(|token| token.to_lowercase())
+}
+
+/// Groups amounts by sign and order of magnitude in half-decade steps.
+fn amount_bucket(amount: f32) -> String {
+    let sign = if amount < 0.0 { "-" } else { "+" };
+    let magnitude = (amount.abs().max(1.0).log10() * 2.0).floor() as i32;
+    format!("{}{}", sign, magnitude)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(partner_name: &str, description: &str, amount: f32) -> TransactionRecord {
+        TransactionRecord {
+            partner_name: partner_name.to_string(),
+            description: description.to_string(),
+            total_amount: amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_predict() {
+        let groceries = Thing::from(("tag", "groceries"));
+        let fuel = Thing::from(("tag", "fuel"));
+        let samples = vec![
+            (
+                transaction("ALDI GmbH", "ALDI SAGT DANKE", -30.84),
+                &groceries,
+            ),
+            (
+                transaction("REWE Markt", "REWE SAGT DANKE", -22.10),
+                &groceries,
+            ),
+            (transaction("PayPal", "SHELL DEUTSCHLAND", -48.32), &fuel),
+            (transaction("Shell", "Tankstelle SHELL", -61.00), &fuel),
+        ]
+        .into_iter()
+        .map(|(t, tag)| (features(&t), tag.clone()))
+        .collect();
+
+        let classifier = TagClassifier::train(samples);
+        let (tag, confidence) = classifier
+            .predict(&features(&transaction("ALDI GmbH", "Einkauf", -12.0)))
+            .unwrap();
+        assert_eq!(tag, groceries);
+        assert!(confidence > 0.5);
+    }
+}
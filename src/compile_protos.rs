@@ -9,13 +9,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     fs::create_dir_all(&out_dir)?;
     tonic_build::configure()
         .out_dir(&out_dir)
-        .message_attribute(".", "#[derive(Deserialize, Serialize)]")
+        .message_attribute(".", "#[derive(Deserialize, Serialize, ToSchema)]")
+        // JSON clients may leave out fields like protobuf does
+        .message_attribute(".", "#[serde(default)]")
         .file_descriptor_set_path(out_dir.join("reflection.bin"))
         .build_server(true)
         .build_client(false)
         .compile_protos(&["moneyview.proto"], &["proto"])?;
 
-    let string_to_add = "use serde::{Serialize, Deserialize};";
+    let string_to_add = "use serde::{Serialize, Deserialize};\nuse utoipa::ToSchema;\n";
     // Lese den Ordner und iteriere über jede Datei
     for entry in fs::read_dir(out_dir)? {
         let entry = entry?;
@@ -0,0 +1,475 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::error::{Error, Result};
+use crate::server::{CorsSettings, TlsFiles};
+
+/// File read when no configuration file is given explicitly.
+const DEFAULT_CONFIG_FILE: &str = "money-view.toml";
+const DEFAULT_TOKEN_HOURS: i64 = 12;
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+/// Named in the errors of configuration files and values.
+const CONFIG_FIELD: &str = "configuration";
+
+/// Command line flags. Every flag can also be given as environment variable, the flags
+/// and variables override the values of the configuration file.
+#[derive(Debug, Parser)]
+#[command(version, about = "MoneyView server")]
+pub(crate) struct Args {
+    /// TOML configuration file [default: money-view.toml, if present]
+    #[arg(short, long, env = "MONEY_VIEW_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address the HTTP and gRPC server listens on
+    #[arg(long, env = "MONEY_VIEW_WEB_HOST")]
+    bind: Option<String>,
+    /// Directory with the built web app
+    #[arg(long, env = "MONEY_VIEW_WEB_DIR")]
+    web_dir: Option<PathBuf>,
+    /// Seconds in-flight requests and jobs get to finish on SIGTERM or Ctrl+C
+    #[arg(long, env = "MONEY_VIEW_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: Option<u64>,
+    /// PEM certificate chain, enables TLS together with --tls-key
+    #[arg(long, env = "MONEY_VIEW_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+    /// PEM private key of the certificate
+    #[arg(long, env = "MONEY_VIEW_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+    /// Allowed CORS origins, comma separated or *
+    #[arg(long, env = "MONEY_VIEW_CORS_ORIGINS", value_delimiter = ',')]
+    cors_origins: Vec<String>,
+    /// Allowed CORS methods, comma separated or *
+    #[arg(long, env = "MONEY_VIEW_CORS_METHODS", value_delimiter = ',')]
+    cors_methods: Vec<String>,
+    /// Allowed CORS request headers, comma separated or *
+    #[arg(long, env = "MONEY_VIEW_CORS_HEADERS", value_delimiter = ',')]
+    cors_headers: Vec<String>,
+    /// Protocol used to connect to SurrealDB
+    #[arg(long, env = "MONEY_VIEW_DB_ENGINE")]
+    db_engine: Option<DbEngine>,
+    /// SurrealDB host and port
+    #[arg(long, env = "MONEY_VIEW_DB_HOST")]
+    db_host: Option<String>,
+    #[arg(long, env = "MONEY_VIEW_DB_NAMESPACE")]
+    db_namespace: Option<String>,
+    #[arg(long, env = "MONEY_VIEW_DB_NAME")]
+    db_name: Option<String>,
+    /// SurrealDB root user
+    #[arg(long, env = "MONEY_VIEW_USER")]
+    db_user: Option<String>,
+    #[arg(long, env = "MONEY_VIEW_DB_PASSWD", hide_env_values = true)]
+    db_password: Option<String>,
+    /// Secret the session tokens are signed with
+    #[arg(long, env = "MONEY_VIEW_JWT_SECRET", hide_env_values = true)]
+    jwt_secret: Option<String>,
+    /// Lifetime of session tokens in hours
+    #[arg(long, env = "MONEY_VIEW_TOKEN_HOURS")]
+    token_hours: Option<i64>,
+    /// Admin account created on first start
+    #[arg(long, env = "MONEY_VIEW_ADMIN_USER")]
+    admin_user: Option<String>,
+    #[arg(long, env = "MONEY_VIEW_ADMIN_PASSWD", hide_env_values = true)]
+    admin_password: Option<String>,
+    /// Folders with MT940 files imported on start, comma separated
+    #[arg(long, env = "MONEY_VIEW_IMPORT_FOLDERS", value_delimiter = ',')]
+    import_folders: Vec<PathBuf>,
+    /// User the accounts of imported files are assigned to [default: admin user]
+    #[arg(long, env = "MONEY_VIEW_IMPORT_OWNER")]
+    import_owner: Option<String>,
+    /// Log level (trace, debug, info, warn, error) or filter directives like RUST_LOG
+    #[arg(long, env = "MONEY_VIEW_LOG")]
+    log_level: Option<String>,
+    #[arg(long, env = "MONEY_VIEW_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+    /// Hide IBANs, amounts and other personal data in logs
+    #[arg(long, env = "MONEY_VIEW_LOG_REDACT")]
+    log_redact: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum DbEngine {
+    /// Plain websocket
+    #[default]
+    Ws,
+    /// Websocket over TLS
+    Wss,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) server: ServerConfig,
+    pub(crate) database: DatabaseConfig,
+    pub(crate) auth: AuthConfig,
+    pub(crate) import: ImportConfig,
+    pub(crate) log: LogConfig,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ServerConfig {
+    pub(crate) bind: String,
+    pub(crate) web_dir: PathBuf,
+    pub(crate) shutdown_timeout: u64, // Seconds
+    pub(crate) tls: Option<TlsFiles>,
+    pub(crate) cors: CorsSettings,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0:8080".to_string(),
+            web_dir: PathBuf::from("./web"),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            tls: None,
+            cors: CorsSettings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct DatabaseConfig {
+    pub(crate) engine: DbEngine,
+    pub(crate) host: String,
+    pub(crate) namespace: String,
+    pub(crate) name: String,
+    pub(crate) user: String,
+    pub(crate) password: String,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct AuthConfig {
+    pub(crate) jwt_secret: String,
+    pub(crate) token_hours: i64,
+    pub(crate) admin_user: Option<String>,
+    pub(crate) admin_password: Option<String>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            jwt_secret: String::new(),
+            token_hours: DEFAULT_TOKEN_HOURS,
+            admin_user: None,
+            admin_password: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ImportConfig {
+    pub(crate) folders: Vec<PathBuf>,
+    pub(crate) owner: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogFormat {
+    /// One line per event
+    #[default]
+    Text,
+    /// Multi-line, for development
+    Pretty,
+    /// One JSON object per event, for log collectors
+    Json,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LogConfig {
+    pub(crate) level: String,
+    pub(crate) format: LogFormat,
+    pub(crate) redact: bool,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::default(),
+            redact: true,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the configuration file, applies environment and command line and validates
+    /// the result.
+    pub(crate) fn load() -> Result<Self> {
+        Self::from_args(Args::parse())
+    }
+
+    fn from_args(args: Args) -> Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::read(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::read(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        config.apply(args)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            Error::invalid_argument(
+                CONFIG_FIELD,
+                format!("cannot read {}: {}", path.display(), e),
+            )
+        })?;
+        Self::parse(&content).map_err(|e| {
+            Error::invalid_argument(
+                CONFIG_FIELD,
+                format!("{} is invalid: {}", path.display(), e),
+            )
+        })
+    }
+
+    fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    fn apply(&mut self, args: Args) -> Result<()> {
+        set(&mut self.server.bind, args.bind);
+        set_path(&mut self.server.web_dir, args.web_dir);
+        if let Some(timeout) = args.shutdown_timeout {
+            self.server.shutdown_timeout = timeout;
+        }
+        match (non_empty_path(args.tls_cert), non_empty_path(args.tls_key)) {
+            (Some(cert), Some(key)) => self.server.tls = Some(TlsFiles { cert, key }),
+            (None, None) => {}
+            _ => {
+                return Err(Error::invalid_argument(
+                    "--tls-cert",
+                    "--tls-cert and --tls-key have to be given together",
+                ))
+            }
+        }
+        set_list(&mut self.server.cors.origins, args.cors_origins);
+        set_list(&mut self.server.cors.methods, args.cors_methods);
+        set_list(&mut self.server.cors.headers, args.cors_headers);
+
+        if let Some(engine) = args.db_engine {
+            self.database.engine = engine;
+        }
+        set(&mut self.database.host, args.db_host);
+        set(&mut self.database.namespace, args.db_namespace);
+        set(&mut self.database.name, args.db_name);
+        set(&mut self.database.user, args.db_user);
+        set(&mut self.database.password, args.db_password);
+
+        set(&mut self.auth.jwt_secret, args.jwt_secret);
+        if let Some(hours) = args.token_hours {
+            self.auth.token_hours = hours;
+        }
+        set_option(&mut self.auth.admin_user, args.admin_user);
+        set_option(&mut self.auth.admin_password, args.admin_password);
+
+        let folders: Vec<PathBuf> = args
+            .import_folders
+            .into_iter()
+            .filter(|folder| !folder.as_os_str().is_empty())
+            .collect();
+        if !folders.is_empty() {
+            self.import.folders = folders;
+        }
+        set_option(&mut self.import.owner, args.import_owner);
+
+        set(&mut self.log.level, args.log_level);
+        if let Some(format) = args.log_format {
+            self.log.format = format;
+        }
+        if let Some(redact) = args.log_redact {
+            self.log.redact = redact;
+        }
+        Ok(())
+    }
+
+    /// Checks all values and reports every problem at once.
+    fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if self.server.bind.parse::<SocketAddr>().is_err() {
+            problems.push(format!(
+                "server.bind: '{}' is no socket address like 0.0.0.0:8080",
+                self.server.bind
+            ));
+        }
+        if !self.server.web_dir.is_dir() {
+            problems.push(format!(
+                "server.web_dir: {} is no directory",
+                self.server.web_dir.display()
+            ));
+        }
+        if let Some(tls) = &self.server.tls {
+            for (name, path) in [("cert", &tls.cert), ("key", &tls.key)] {
+                if !path.is_file() {
+                    problems.push(format!("server.tls.{}: {} not found", name, path.display()));
+                }
+            }
+        }
+        if let Err(e) = self.server.cors.layer() {
+            problems.push(format!("server.cors: {}", e));
+        }
+
+        let required = [
+            ("database.host (MONEY_VIEW_DB_HOST)", &self.database.host),
+            (
+                "database.namespace (MONEY_VIEW_DB_NAMESPACE)",
+                &self.database.namespace,
+            ),
+            ("database.name (MONEY_VIEW_DB_NAME)", &self.database.name),
+            ("database.user (MONEY_VIEW_USER)", &self.database.user),
+            (
+                "database.password (MONEY_VIEW_DB_PASSWD)",
+                &self.database.password,
+            ),
+            (
+                "auth.jwt_secret (MONEY_VIEW_JWT_SECRET)",
+                &self.auth.jwt_secret,
+            ),
+        ];
+        for (name, value) in required {
+            if value.is_empty() {
+                problems.push(format!("{} is missing", name));
+            }
+        }
+        if self.auth.token_hours <= 0 {
+            problems.push("auth.token_hours has to be positive".to_string());
+        }
+        if self.auth.admin_user.is_some() != self.auth.admin_password.is_some() {
+            problems.push(
+                "auth.admin_user and auth.admin_password have to be set together".to_string(),
+            );
+        }
+
+        for folder in &self.import.folders {
+            if !folder.is_dir() {
+                problems.push(format!(
+                    "import.folders: {} is no directory",
+                    folder.display()
+                ));
+            }
+        }
+        if !self.import.folders.is_empty() && self.import_owner().is_none() {
+            problems
+                .push("import.owner or auth.admin_user is needed for import folders".to_string());
+        }
+
+        if let Err(e) = EnvFilter::try_new(&self.log.level) {
+            problems.push(format!("log.level: '{}' is invalid: {}", self.log.level, e));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::invalid_argument(
+                CONFIG_FIELD,
+                format!("\n  {}", problems.join("\n  ")),
+            ))
+        }
+    }
+
+    /// User the accounts found in the import folders belong to.
+    pub(crate) fn import_owner(&self) -> Option<&str> {
+        self.import
+            .owner
+            .as_deref()
+            .or(self.auth.admin_user.as_deref())
+    }
+}
+
+// Empty values, e.g. from `VAR=` in a compose file, count as not set.
+fn set(target: &mut String, value: Option<String>) {
+    if let Some(value) = value.filter(|v| !v.is_empty()) {
+        *target = value;
+    }
+}
+
+fn set_option(target: &mut Option<String>, value: Option<String>) {
+    if let Some(value) = value.filter(|v| !v.is_empty()) {
+        *target = Some(value);
+    }
+}
+
+fn set_path(target: &mut PathBuf, value: Option<PathBuf>) {
+    if let Some(value) = non_empty_path(value) {
+        *target = value;
+    }
+}
+
+fn set_list(target: &mut Vec<String>, values: Vec<String>) {
+    let values: Vec<String> = values.into_iter().filter(|v| !v.is_empty()).collect();
+    if !values.is_empty() {
+        *target = values;
+    }
+}
+
+fn non_empty_path(path: Option<PathBuf>) -> Option<PathBuf> {
+    path.filter(|path| !path.as_os_str().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layers() {
+        let mut config = Config::parse(
+            r#"
+            [server]
+            bind = "127.0.0.1:9000"
+            web_dir = "src"
+            cors = { origins = ["https://money.example"] }
+
+            [database]
+            engine = "wss"
+            host = "db.example:443"
+            namespace = "money"
+            name = "view"
+            user = "root"
+            password = "from file"
+
+            [auth]
+            jwt_secret = "secret"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.database.engine, DbEngine::Wss);
+        assert_eq!(config.auth.token_hours, DEFAULT_TOKEN_HOURS);
+        assert!(Config::parse("[server]\nport = 80").is_err());
+
+        let args = Args::try_parse_from([
+            "money-view",
+            "--bind",
+            "0.0.0.0:8443",
+            "--db-password",
+            "from flag",
+            "--db-name",
+            "",
+            "--cors-methods",
+            "GET,POST",
+        ])
+        .unwrap();
+        config.apply(args).unwrap();
+        assert_eq!(config.server.bind, "0.0.0.0:8443");
+        assert_eq!(config.database.password, "from flag");
+        assert_eq!(config.database.name, "view");
+        assert_eq!(config.server.cors.methods, vec!["GET", "POST"]);
+        assert!(config.validate().is_ok());
+
+        config.database.host.clear();
+        config.log.level = "money_view=loud".to_string();
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("database.host"));
+        assert!(error.contains("log.level"));
+    }
+}
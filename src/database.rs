@@ -1,72 +1,208 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
-use crate::api::{self, BalanceInformation, LineItem, Transaction, TransactionPartner};
-use crate::ShortResult;
-use chrono::NaiveDate;
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use crate::access::Access;
+use crate::anomaly::{self, AnomalySettings};
+use crate::api::{
+    self, AccountForecast, AccountRole, Anomaly, AnomalyKind, BalanceHistoryResponse,
+    BalanceInformation, BudgetStatus, CashFlowBreakdown, CashFlowPeriod, CashFlowPoint, LineItem,
+    ListTransactionsResponse, SearchMatch, TagSuggestion, TagTestMatch, TagTestResponse,
+    Transaction, TransactionPartner, TransactionSort, TransferStatus,
+};
+use crate::balance;
+use crate::budget;
+use crate::classifier::{self, TagClassifier};
+use crate::config::{DatabaseConfig, DbEngine};
+use crate::error::{Error, Result};
+use crate::exchange::RateTable;
+use crate::forecast;
+use crate::logging::sensitive;
+use crate::recurring;
+use crate::transfer;
+use chrono::{Datelike, NaiveDate};
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
-use surrealdb::engine::remote::ws::{Client, Ws};
+use surrealdb::engine::any::{self, Any};
 use surrealdb::opt::auth::Root;
-use surrealdb::sql::Thing;
+use surrealdb::sql::{Id, Thing};
 use surrealdb::Surreal;
+use tracing::{info, instrument, trace, warn};
 
-const DEFAULT_TAG_ID: (&'static str, &'static str) = ("tag", "default");
+const CONNECTION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+const DEFAULT_TAG_ID: (&str, &str) = ("tag", "default");
+const SEARCH_SCHEMA: &str = "
+DEFINE ANALYZER IF NOT EXISTS transaction_text TOKENIZERS blank, class, punct FILTERS lowercase, ascii, snowball(german);
+DEFINE INDEX IF NOT EXISTS transaction_description_search ON transaction FIELDS description SEARCH ANALYZER transaction_text BM25 HIGHLIGHTS;
+DEFINE INDEX IF NOT EXISTS transaction_partner_search ON transaction FIELDS partner_name SEARCH ANALYZER transaction_text BM25 HIGHLIGHTS;
+";
+/// Conversion between currencies in aggregates, based on the imported ECB rates per euro.
+/// Amounts without currency are in euro. Dates before the first quote use the oldest rate.
+const CURRENCY_SCHEMA: &str = "
+DEFINE INDEX IF NOT EXISTS exchange_rate_lookup ON exchange_rate FIELDS currency, date;
+DEFINE FUNCTION IF NOT EXISTS fn::per_euro($currency: option<string>, $date: any) {
+    IF !$currency OR $currency = 'EUR' { RETURN 1; };
+    LET $rate = (SELECT rate, date FROM exchange_rate WHERE currency = $currency AND date <= $date ORDER BY date DESC LIMIT 1)[0].rate
+        ?? (SELECT rate, date FROM exchange_rate WHERE currency = $currency ORDER BY date LIMIT 1)[0].rate;
+    IF $rate = NONE { THROW 'no exchange rate for ' + $currency; };
+    RETURN $rate;
+};
+DEFINE FUNCTION IF NOT EXISTS fn::convert($amount: number, $from: option<string>, $to: option<string>, $date: any) {
+    RETURN $amount / fn::per_euro($from, $date) * fn::per_euro($to, $date);
+};
+";
+const EXCHANGE_RATE_CHUNK_SIZE: usize = 1000;
+/// Transactions of linked transfers, selected once before an aggregate instead of for every
+/// row, see [`TransactionFilter::aggregate_conditions`]. The aggregate is the next statement.
+const LINKED_TRANSFERS: &str = "LET $linked_transfers = IF $include_transfers { [] } ELSE {
+    array::flatten((SELECT VALUE [debit_id, credit_id] FROM transfer WHERE status != $rejected))
+};
+";
+const HIGHLIGHT_START: &str = "<b>";
+const HIGHLIGHT_END: &str = "</b>";
+/// Field of `ListTransactionsRequest` named when a cursor cannot be read.
+const CURSOR_FIELD: &str = "cursor";
 
-#[derive(Debug)]
+/// Handle to SurrealDB, cheap to clone. The connection behind it is replaced when it is
+/// lost, see [`Database::keep_connected`].
+#[derive(Clone)]
 pub(crate) struct Database {
-    db: Surreal<Client>,
+    connection: Arc<RwLock<Surreal<Any>>>,
+    config: Arc<DatabaseConfig>,
+}
+
+impl std::fmt::Debug for Database {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Database")
+            .field("host", &self.config.host)
+            .field("namespace", &self.config.namespace)
+            .field("name", &self.config.name)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Database {
-    pub(crate) async fn new(
-        host: String,
-        user_name: String,
-        password: String,
-        namespace: String,
-        database: String,
-    ) -> surrealdb::Result<Self> {
-        let db = Surreal::new::<Ws>(host).await?;
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn new(config: &DatabaseConfig) -> Result<Self> {
+        let db = Self::connect(config).await?;
+        Ok(Self {
+            connection: Arc::new(RwLock::new(db)),
+            config: Arc::new(config.clone()),
+        })
+    }
+
+    /// Empty in-memory database with the schema of a fresh server.
+    #[cfg(test)]
+    pub(crate) async fn memory() -> Self {
+        let db = any::connect("mem://").await.unwrap();
+        db.use_ns("test").use_db("test").await.unwrap();
+        let database = Self {
+            connection: Arc::new(RwLock::new(db)),
+            config: Arc::new(DatabaseConfig::default()),
+        };
+        database.init_db().await.unwrap();
+        database
+    }
+
+    /// Opens a connection, signs in and selects namespace and database.
+    async fn connect(config: &DatabaseConfig) -> Result<Surreal<Any>> {
+        let scheme = match config.engine {
+            DbEngine::Ws => "ws",
+            DbEngine::Wss => "wss",
+        };
+        let db = any::connect(format!("{}://{}", scheme, config.host)).await?;
         // Signin as a namespace, database, or root user
-        let result = db
-            .signin(Root {
-                username: &user_name,
-                password: &password,
-            })
+        db.signin(Root {
+            username: &config.user,
+            password: &config.password,
+        })
+        .await?;
+        info!(host = %config.host, namespace = %config.namespace, database = %config.name, "signed in to SurrealDB");
+        // Select a specific namespace / database
+        db.use_ns(config.namespace.as_str())
+            .use_db(config.name.as_str())
             .await?;
+        Ok(db)
+    }
 
-        dbg!(result);
-        // Select a specific namespace / database
-        db.use_ns(namespace).use_db(database).await?;
-        Ok(Self { db })
+    /// Current connection. Clone it out of the lock, a reconnect swaps it underneath.
+    fn db(&self) -> Surreal<Any> {
+        match self.connection.read() {
+            Ok(db) => db.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    /// Checks the connection periodically and reconnects with exponential backoff once
+    /// SurrealDB stops answering. Queries in the meantime fail with the connection error.
+    pub(crate) fn keep_connected(&self) {
+        let database = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(CONNECTION_CHECK_INTERVAL).await;
+                let alive = matches!(
+                    tokio::time::timeout(CONNECTION_TIMEOUT, database.ping()).await,
+                    Ok(Ok(()))
+                );
+                if !alive {
+                    warn!(host = %database.config.host, "lost connection to SurrealDB");
+                    database.reconnect().await;
+                }
+            }
+        });
+    }
+
+    async fn reconnect(&self) {
+        let mut delay = RECONNECT_MIN_DELAY;
+        loop {
+            match tokio::time::timeout(CONNECTION_TIMEOUT, Self::connect(&self.config)).await {
+                Ok(Ok(db)) => {
+                    match self.connection.write() {
+                        Ok(mut connection) => *connection = db,
+                        Err(poisoned) => *poisoned.into_inner() = db,
+                    }
+                    info!(host = %self.config.host, "reconnected to SurrealDB");
+                    return;
+                }
+                Ok(Err(e)) => warn!(error = %e, retry_in = ?delay, "reconnect failed"),
+                Err(_) => warn!(retry_in = ?delay, "reconnect timed out"),
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+        }
     }
 
-    pub(crate) async fn init_db(&self) -> ShortResult<()> {
-        let default: Option<Tag> = self.db.select(DEFAULT_TAG_ID).await?;
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn init_db(&self) -> Result<()> {
+        let default: Option<Tag> = self.db().select(DEFAULT_TAG_ID).await?;
         if default.is_none() {
             let defaut_tag = Tag {
                 id: Thing::from(DEFAULT_TAG_ID),
                 name: String::from("Sonstige"),
                 keywords: Vec::new(),
             };
-            let _result: Option<Tag> = self.db.create(DEFAULT_TAG_ID).content(defaut_tag).await?;
+            let _result: Option<Tag> = self.db().create(DEFAULT_TAG_ID).content(defaut_tag).await?;
         }
+        self.db().query(SEARCH_SCHEMA).await?.check()?;
+        self.db().query(CURRENCY_SCHEMA).await?.check()?;
         Ok(())
     }
 
-    pub(crate) async fn save_transaction(
-        &self,
-        transaction: TransactionRecord,
-    ) -> surrealdb::Result<()> {
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn save_transaction(&self, transaction: TransactionRecord) -> Result<()> {
         let result: std::prelude::v1::Option<TransactionRecord> = self
-            .db
+            .db()
             .select((
                 transaction.id.tb.clone(),
                 transaction.id.id.clone().to_raw(),
             ))
             .await?;
-        if let Some(_) = result {
+        if result.is_some() {
             let _result: std::prelude::v1::Option<TransactionRecord> = self
-                .db
+                .db()
                 .update((
                     transaction.id.tb.clone(),
                     transaction.id.id.clone().to_raw(),
@@ -75,7 +211,7 @@ impl Database {
                 .await?;
         } else {
             let _result: std::prelude::v1::Option<TransactionRecord> = self
-                .db
+                .db()
                 .create((
                     transaction.id.tb.clone(),
                     transaction.id.id.clone().to_raw(),
@@ -85,66 +221,234 @@ impl Database {
         }
         Ok(())
     }
-    pub(crate) async fn get_all_transactions(&self) -> surrealdb::Result<Vec<Transaction>> {
-        let transactions: Vec<QueryResult> = self.db.query("Select id,date,total_amount,partner_name,description, line_items.tag_id.name as tags from transaction;").await?.take(0)?;
-        Ok(transactions
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_all_transactions(&self, access: &Access) -> Result<Vec<Transaction>> {
+        let page = self
+            .transaction_page(
+                TransactionFilter::default().scoped(access),
+                TransactionSort::DateDesc,
+                None,
+                None,
+            )
+            .await?;
+        Ok(page.transactions)
+    }
+
+    /// Returns one page of transactions using keyset pagination together with the number
+    /// of all matching transactions.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn list_transactions(
+        &self,
+        filter: TransactionFilter,
+        sort: TransactionSort,
+        page_size: Option<u32>,
+        cursor: Option<String>,
+    ) -> Result<ListTransactionsResponse> {
+        let count: Option<CountResult> = self
+            .db()
+            .query(format!(
+                "select count() as count from transaction {}group all;",
+                where_clause(&filter.listing_conditions())
+            ))
+            .bind(filter.clone())
+            .await?
+            .take(0)?;
+        let mut page = self
+            .transaction_page(filter, sort, page_size, cursor)
+            .await?;
+        page.total_count = count.map(|c| c.count).unwrap_or_default();
+        Ok(page)
+    }
+
+    /// Returns one page of transactions using keyset pagination, without counting all
+    /// matches. The cursor holds the sort value and id of the last transaction of the
+    /// previous page.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn transaction_page(
+        &self,
+        filter: TransactionFilter,
+        sort: TransactionSort,
+        page_size: Option<u32>,
+        cursor: Option<String>,
+    ) -> Result<ListTransactionsResponse> {
+        let (field, direction, operator) = match sort {
+            TransactionSort::DateDesc => ("date", "desc", "<"),
+            TransactionSort::DateAsc => ("date", "asc", ">"),
+            TransactionSort::AmountDesc => ("total_amount", "desc", "<"),
+            TransactionSort::AmountAsc => ("total_amount", "asc", ">"),
+        };
+        let mut conditions = filter.listing_conditions();
+
+        let cursor = cursor.map(|cursor| parse_cursor(&cursor)).transpose()?;
+        let cursor_condition = format!(
+            "({field}{operator}$cursor_key or ({field}=$cursor_key and id{operator}$cursor_id))"
+        );
+        if cursor.is_some() {
+            conditions.push(cursor_condition.as_str());
+        }
+        let limit = page_size.map(|size| format!("limit {}", size + 1));
+        let page_query = format!(
+            "Select id,date,total_amount,balance_after_transaction,currency,original_currency,original_amount,partner_name,description, line_items.tag_id.name as tags from transaction {}order by {field} {direction}, id {direction} {};",
+            where_clause(&conditions),
+            limit.unwrap_or_default()
+        );
+
+        let db = self.db();
+        let mut query = db.query(page_query).bind(filter);
+        if let Some((key, id)) = cursor {
+            query = if sort == TransactionSort::DateAsc || sort == TransactionSort::DateDesc {
+                let key = NaiveDate::parse_from_str(&key, "%Y-%m-%d")
+                    .map_err(|e| Error::invalid_argument(CURSOR_FIELD, e))?;
+                query.bind(("cursor_key", key))
+            } else {
+                let key = key
+                    .parse::<f64>()
+                    .map_err(|e| Error::invalid_argument(CURSOR_FIELD, e))?;
+                query.bind(("cursor_key", key))
+            };
+            query = query.bind(("cursor_id", id));
+        }
+        let mut transactions: Vec<QueryResult> = query.await?.take(0)?;
+
+        let mut next_cursor = String::new();
+        if let Some(size) = page_size {
+            if transactions.len() > size as usize {
+                transactions.truncate(size as usize);
+                if let Some(last) = transactions.last() {
+                    let key = match sort {
+                        TransactionSort::DateDesc | TransactionSort::DateAsc => {
+                            last.date.to_string()
+                        }
+                        TransactionSort::AmountDesc | TransactionSort::AmountAsc => {
+                            last.total_amount.to_string()
+                        }
+                    };
+                    next_cursor = format_cursor(&key, &last.id);
+                }
+            }
+        }
+
+        Ok(ListTransactionsResponse {
+            transactions: transactions.into_iter().map(|res| res.into()).collect(),
+            next_cursor,
+            total_count: 0,
+        })
+    }
+
+    /// Full-text search over description and partner name, best matches first.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn search_transactions(
+        &self,
+        query: String,
+        limit: u32,
+        access: &Access,
+    ) -> Result<Vec<SearchMatch>> {
+        let results: Vec<SearchResult> = self
+            .db()
+            .query(
+                "Select id,date,total_amount,balance_after_transaction,currency,original_currency,original_amount,partner_name,description, line_items.tag_id.name as tags,
+                search::highlight($start, $end, 0) as description_highlight,
+                search::highlight($start, $end, 1) as partner_highlight,
+                (search::score(0) ?? 0) + (search::score(1) ?? 0) as score
+                from transaction where (description @0@ $query or partner_name @1@ $query)
+                and account_id in $scope
+                order by score desc limit $limit;",
+            )
+            .bind(("query", query))
+            .bind(("scope", access.readable_accounts()))
+            .bind(("limit", limit))
+            .bind(("start", HIGHLIGHT_START))
+            .bind(("end", HIGHLIGHT_END))
+            .await?
+            .take(0)?;
+        Ok(results
             .into_iter()
-            .map(|res| Transaction {
-                id: res.id.to_raw(),
-                date: (res.date - NaiveDate::default()).num_days(),
-                total_amount: res.total_amount as f32,
-                partner_name: res.partner_name,
-                description: res.description,
-                tags: res.tags,
+            .map(|res| SearchMatch {
+                score: res.score as f32,
+                description_highlight: res.description_highlight.unwrap_or(res.description.clone()),
+                partner_highlight: res.partner_highlight.unwrap_or(res.partner_name.clone()),
+                transaction: Some(
+                    QueryResult {
+                        id: res.id,
+                        date: res.date,
+                        total_amount: res.total_amount,
+                        balance_after_transaction: res.balance_after_transaction,
+                        currency: res.currency,
+                        original_currency: res.original_currency,
+                        original_amount: res.original_amount,
+                        partner_name: res.partner_name,
+                        description: res.description,
+                        tags: res.tags,
+                    }
+                    .into(),
+                ),
             })
             .collect())
     }
 
-    pub(crate) async fn get_all_transaction_partners(
-        &self,
-    ) -> ShortResult<Vec<TransactionPartner>> {
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_all_transaction_partners(&self) -> Result<Vec<TransactionPartner>> {
         let partners: Vec<String> = Vec::new();
         Ok(partners
             .into_iter()
             .map(|p| TransactionPartner { name: p })
             .collect())
     }
-    pub(crate) async fn save_all(&self, transactions: Vec<TransactionRecord>) -> ShortResult<()> {
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn save_all(&self, transactions: Vec<TransactionRecord>) -> Result<()> {
         let mut transaction_count = 0u32;
         let tags = self.get_tag_map().await?;
         for mut transaction in transactions {
             transaction = transaction.update_tags(&tags);
+            trace!(
+                account = %sensitive(&transaction.account_id),
+                partner = %sensitive(&transaction.partner_id),
+                amount = %sensitive(transaction.total_amount),
+                "saving transaction"
+            );
             self.save_transaction(transaction).await?;
             transaction_count += 1;
         }
-        println!("Inserted:\n {transaction_count} transactions");
+        info!(transactions = transaction_count, "saved transactions");
         Ok(())
     }
 
+    #[instrument(level = "debug", skip_all, err)]
     pub(crate) async fn get_partner_balance(
         &self,
         positive: bool,
-    ) -> surrealdb::Result<Vec<BalanceInformation>> {
-        const BASE_QUERY: &'static str ="Select math::Sum(total_amount) as balance,partner_name as name, count() as transaction_count from transaction ";
-        const POSITIVE: &'static str = "where total_amount>0.0 ";
-        const NEGATIVE: &'static str = "where total_amount<0.0 ";
-        const GROUP: &'static str = "group name;";
+        filter: TransactionFilter,
+    ) -> Result<Vec<BalanceInformation>> {
+        // converted once per partner, currency and day instead of once per transaction
+        const BASE_QUERY: &str ="select math::sum(fn::convert(amount, currency, $currency, date)) as balance, name, math::sum(transaction_count) as transaction_count from (select math::sum(total_amount) as amount, partner_name as name, currency, date, count() as transaction_count from transaction ";
+        const POSITIVE: &str = "total_amount>0.0";
+        const NEGATIVE: &str = "total_amount<0.0";
+        const GROUP: &str = "group name, currency, date) group name;";
+        let mut conditions = vec![if positive { POSITIVE } else { NEGATIVE }];
+        conditions.extend(filter.aggregate_conditions());
+        if !filter.tag_ids.is_empty() {
+            conditions.push("line_items.tag_id containsany $tag_ids");
+        }
         let result: Vec<BalanceInformation> = self
-            .db
+            .db()
             .query(format!(
-                "{}{}{}",
+                "{}{}{}{}",
+                LINKED_TRANSFERS,
                 BASE_QUERY,
-                if positive { POSITIVE } else { NEGATIVE },
+                where_clause(&conditions),
                 GROUP
             ))
+            .bind(filter)
+            .bind(("rejected", TransferStatus::Rejected as i32))
             .await?
-            .take(0)?;
+            .take(1)?;
         Ok(result)
     }
 
-    pub(crate) async fn update_tags(&self) -> ShortResult<()> {
-        let tags = HashMap::new();
-        let mut transactions: Vec<TransactionRecord> = self.get_all_transaction_records().await?;
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn update_tags(&self) -> Result<()> {
+        let tags = self.get_tag_map().await?;
+        let mut transactions = self.get_all_transaction_records().await?;
         let (send, recv) = tokio::sync::oneshot::channel();
         rayon::spawn(move || {
             let result = transactions
@@ -159,12 +463,35 @@ impl Database {
         Ok(())
     }
 
-    async fn get_all_transaction_records(&self) -> ShortResult<Vec<TransactionRecord>> {
-        let transactions: Vec<TransactionRecord> = self.db.select("transaction").await?;
+    #[instrument(level = "debug", skip_all, err)]
+    async fn get_all_transaction_records(&self) -> Result<Vec<TransactionRecord>> {
+        let transactions: Vec<TransactionRecord> = self.db().select("transaction").await?;
+        Ok(transactions)
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    async fn get_transaction_records(
+        &self,
+        account_ids: Vec<String>,
+    ) -> Result<Vec<TransactionRecord>> {
+        let transactions: Vec<TransactionRecord> = self
+            .db()
+            .query("select * from transaction where account_id in $account_ids;")
+            .bind(("account_ids", account_ids))
+            .await?
+            .take(0)?;
         Ok(transactions)
     }
 
-    async fn get_tag_map(&self) -> ShortResult<HashMap<Thing, Vec<String>>> {
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_transaction(&self, id: Thing) -> Result<Option<TransactionRecord>> {
+        let transaction: Option<TransactionRecord> =
+            self.db().select((id.tb.clone(), id.id.to_raw())).await?;
+        Ok(transaction)
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    async fn get_tag_map(&self) -> Result<HashMap<Thing, Vec<String>>> {
         let mut tag_map = HashMap::new();
         let tags: Vec<Tag> = self.get_tags().await?;
         for tag in tags {
@@ -173,174 +500,2224 @@ impl Database {
         Ok(tag_map)
     }
 
-    pub(crate) async fn get_tags(&self) -> ShortResult<Vec<Tag>> {
-        let result: Vec<Tag> = self.db.select("tag").await?;
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_tags(&self) -> Result<Vec<Tag>> {
+        let result: Vec<Tag> = self.db().select("tag").await?;
         Ok(result)
     }
 
-    pub(crate) async fn save_tag(&self, tag: Tag) -> ShortResult<()> {
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn save_tag(&self, tag: Tag) -> Result<()> {
         let id = (tag.id.tb.clone(), tag.id.id.clone().to_raw());
 
-        let result: Option<Tag> = self.db.select(id.clone()).await?;
+        let result: Option<Tag> = self.db().select(id.clone()).await?;
 
-        if let Some(_) = result {
-            let _result: Option<Tag> = self.db.update(id.clone()).content(tag).await?;
+        if result.is_some() {
+            let _result: Option<Tag> = self.db().update(id.clone()).content(tag).await?;
         } else {
-            let _result: Option<Tag> = self.db.create(id.clone()).content(tag).await?;
+            let _result: Option<Tag> = self.db().create(id.clone()).content(tag).await?;
         }
 
         Ok(())
     }
-    pub(crate) async fn get_tag_balance(
+
+    /// Evaluates the keywords of `candidate` against all transactions without persisting
+    /// anything.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn test_tag(
         &self,
-        positive: bool,
-    ) -> surrealdb::Result<Vec<BalanceInformation>> {
-        const BASE_QUERY: &'static str ="select math::sum(line_items.amount) as balance, line_items.tag_id.name as name, count() as transaction_count from(select line_items from transaction split line_items) ";
-        const POSITIVE: &'static str = "where line_items.amount>0.0 ";
-        const NEGATIVE: &'static str = "where line_items.amount<0.0 ";
-        const GROUP: &'static str = "group name;";
-        let result: Vec<BalanceInformation> = self
-            .db
-            .query(format!(
-                "{}{}{}",
-                BASE_QUERY,
-                if positive { POSITIVE } else { NEGATIVE },
-                GROUP
-            ))
+        candidate: Tag,
+        access: &Access,
+    ) -> Result<TagTestResponse> {
+        let tags = self.get_tags().await?;
+        let transactions = self
+            .get_transaction_records(access.readable_accounts())
+            .await?;
+        let (send, recv) = tokio::sync::oneshot::channel();
+        rayon::spawn(move || {
+            let tag_names: HashMap<Thing, String> = tags
+                .iter()
+                .chain(std::iter::once(&candidate))
+                .map(|tag| (tag.id.clone(), tag.name.clone()))
+                .collect();
+            let others: Vec<&Tag> = tags.iter().filter(|tag| tag.id != candidate.id).collect();
+            let matches: Vec<TagTestMatch> = transactions
+                .into_par_iter()
+                .filter(|t| t.matches_keywords(&candidate.keywords))
+                .map(|t| {
+                    let current_tag_id = t.remaining_tag().map(|id| id.id.to_raw());
+                    let conflicting_tag_ids = others
+                        .iter()
+                        .filter(|tag| t.matches_keywords(&tag.keywords))
+                        .map(|tag| tag.id.id.to_raw())
+                        .collect();
+                    TagTestMatch {
+                        transaction: Some(t.into_api(&tag_names)),
+                        current_tag_id: current_tag_id.unwrap_or_default(),
+                        conflicting_tag_ids,
+                    }
+                })
+                .collect();
+            let default_id = Thing::from(DEFAULT_TAG_ID).id.to_raw();
+            let candidate_id = candidate.id.id.to_raw();
+            let stolen = matches
+                .iter()
+                .filter(|m| m.current_tag_id != default_id && m.current_tag_id != candidate_id)
+                .cloned()
+                .collect();
+            let conflicts = matches
+                .iter()
+                .filter(|m| !m.conflicting_tag_ids.is_empty())
+                .cloned()
+                .collect();
+            let _ = send.send(TagTestResponse {
+                matches,
+                stolen,
+                conflicts,
+            });
+        });
+        Ok(recv.await?)
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_budgets(&self, user_name: &str) -> Result<Vec<Budget>> {
+        let result: Vec<Budget> = self
+            .db()
+            .query("select * from budget where user_name = $user_name;")
+            .bind(("user_name", user_name.to_string()))
             .await?
             .take(0)?;
         Ok(result)
     }
-}
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct Tag {
-    pub(crate) id: Thing,
-    pub(crate) name: String,
-    pub(crate) keywords: Vec<String>,
-}
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn save_budget(&self, budget: Budget) -> Result<()> {
+        let tag: Option<Tag> = self
+            .db()
+            .select((budget.tag_id.tb.clone(), budget.tag_id.id.to_raw()))
+            .await?;
+        if tag.is_none() {
+            return Err(Error::NotFound(format!(
+                "tag {} does not exist",
+                budget.tag_id.id.to_raw()
+            )));
+        }
+        let id = (budget.id.tb.clone(), budget.id.id.clone().to_raw());
 
-impl From<api::Tag> for Tag {
-    fn from(value: api::Tag) -> Self {
-        Self {
-            id: Thing::from(("tag", value.id.as_str())),
-            name: value.name,
-            keywords: value.key_words,
+        let result: Option<Budget> = self.db().select(id.clone()).await?;
+
+        if let Some(existing) = result {
+            if existing.user_name != budget.user_name {
+                return Err(Error::PermissionDenied(
+                    "budget belongs to another user".to_string(),
+                ));
+            }
+            let _result: Option<Budget> = self.db().update(id.clone()).content(budget).await?;
+        } else {
+            let _result: Option<Budget> = self.db().create(id.clone()).content(budget).await?;
         }
+
+        Ok(())
     }
-}
 
-impl Into<api::Tag> for Tag {
-    fn into(self) -> api::Tag {
-        api::Tag {
-            id: self.id.id.to_raw(),
-            name: self.name,
-            key_words: self.keywords,
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn delete_budget(&self, id: Thing, user_name: &str) -> Result<()> {
+        self.db()
+            .query("DELETE $id WHERE user_name = $user_name;")
+            .bind(("id", id))
+            .bind(("user_name", user_name.to_string()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Computes spent, remaining and projected amounts of every budget on `date`.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_budget_status(
+        &self,
+        date: NaiveDate,
+        access: &Access,
+    ) -> Result<Vec<BudgetStatus>> {
+        let mut statuses = Vec::new();
+        for budget in self.get_budgets(&access.user_name).await? {
+            let period = budget.period();
+            let filter = TransactionFilter {
+                from: Some(budget::period_bounds(budget.start_date, period).0),
+                to: Some(budget::period_bounds(date, period).1 - chrono::Duration::days(1)),
+                tag_ids: vec![budget.tag_id.clone()],
+                ..Default::default()
+            }
+            .scoped(access);
+            let series = self
+                .get_cash_flow_series(period, CashFlowBreakdown::None, filter)
+                .await?;
+            statuses.push(budget::status(&budget, &series, date));
         }
+        Ok(statuses)
     }
-}
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-struct QueryResult {
-    id: Thing,
-    date: NaiveDate,
-    total_amount: f64,
-    partner_name: String,
-    description: String,
-    tags: Vec<String>,
-}
+    /// Replaces the stored recurring payments of the editable accounts with a fresh analysis
+    /// of their transactions.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn detect_recurring_payments(
+        &self,
+        today: NaiveDate,
+        access: &Access,
+    ) -> Result<Vec<RecurringPayment>> {
+        let account_ids = access.editable_accounts();
+        let transactions = self.get_transaction_records(account_ids.clone()).await?;
+        let (send, recv) = tokio::sync::oneshot::channel();
+        rayon::spawn(move || {
+            let _ = send.send(recurring::detect(transactions, today));
+        });
+        let payments: Vec<RecurringPayment> = recv.await?;
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct TransactionRecord {
-    pub(crate) id: Thing,            // Unique identifier for the transaction
-    pub(crate) account_id: String,   // ID of the bank account where the transaction occurred
-    pub(crate) date: NaiveDate,      // Date of the transaction (e.g., "2024-09-04")
-    pub(crate) total_amount: f32,    // Total amount of the transaction
-    pub(crate) partner_name: String, // Reference ID to the transaction partner
-    pub(crate) line_items: Vec<LineItemRecord>, // List of line items within the transaction
-    pub(crate) description: String,  // Description or memo of the transaction
-    pub(crate) balance_after_transaction: f32, // Account balance after the transaction
-}
+        self.db()
+            .query(
+                "BEGIN TRANSACTION;
+                DELETE recurring_payment WHERE account_id IN $account_ids;
+                FOR $payment IN $payments { CREATE $payment.id CONTENT $payment; };
+                COMMIT TRANSACTION;",
+            )
+            .bind(("account_ids", account_ids))
+            .bind(("payments", payments.clone()))
+            .await?
+            .check()?;
+        Ok(payments)
+    }
 
-impl Default for TransactionRecord {
-    fn default() -> Self {
-        Self {
-            id: Thing::from(("transaction", "tmp")),
-            account_id: Default::default(),
-            date: Default::default(),
-            total_amount: Default::default(),
-            partner_name: Default::default(),
-            line_items: Default::default(),
-            description: Default::default(),
-            balance_after_transaction: Default::default(),
-        }
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_recurring_payments(
+        &self,
+        access: &Access,
+    ) -> Result<Vec<RecurringPayment>> {
+        let result: Vec<RecurringPayment> = self
+            .db()
+            .query("select * from recurring_payment where account_id in $account_ids;")
+            .bind(("account_ids", access.readable_accounts()))
+            .await?
+            .take(0)?;
+        Ok(result)
     }
-}
 
-impl TransactionRecord {
-    fn update_tags(mut self, tag_keywords: &HashMap<Thing, Vec<String>>) -> Self {
-        self.line_items = self
-            .line_items
+    /// Projects the balance of every account for the next `months` months.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_forecast(
+        &self,
+        today: NaiveDate,
+        months: u32,
+        low_balance_threshold: f32,
+        access: &Access,
+    ) -> Result<Vec<AccountForecast>> {
+        let transactions = self
+            .get_transaction_records(access.readable_accounts())
+            .await?;
+        let recurring_payments = self.get_recurring_payments(access).await?;
+        let budgets = self.get_budgets(&access.user_name).await?;
+        let days = (today + chrono::Months::new(months) - today).num_days();
+        let (send, recv) = tokio::sync::oneshot::channel();
+        rayon::spawn(move || {
+            let _ = send.send(forecast::forecast(
+                &transactions,
+                &recurring_payments,
+                &budgets,
+                today,
+                days,
+                low_balance_threshold,
+            ));
+        });
+        Ok(recv.await?)
+    }
+
+    /// Lists suspicious transactions, dismissed findings are marked or left out.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_anomalies(
+        &self,
+        settings: AnomalySettings,
+        include_dismissed: bool,
+        access: &Access,
+    ) -> Result<Vec<Anomaly>> {
+        let account_ids = access.readable_accounts();
+        let transactions = self.get_transaction_records(account_ids.clone()).await?;
+        let dismissals: Vec<AnomalyDismissal> = self
+            .db()
+            .query(
+                "select * from anomaly_dismissal where transaction_id.account_id in $account_ids;",
+            )
+            .bind(("account_ids", account_ids))
+            .await?
+            .take(0)?;
+        let tag_names: HashMap<Thing, String> = self
+            .get_tags()
+            .await?
             .into_iter()
-            .filter(|item| item.description != "".to_owned())
+            .map(|tag| (tag.id, tag.name))
             .collect();
+        let (send, recv) = tokio::sync::oneshot::channel();
+        rayon::spawn(move || {
+            let _ = send.send(anomaly::detect(&transactions, settings));
+        });
 
-        let line_amount: f32 = self.line_items.iter().map(|item| item.amount).sum();
+        Ok(recv
+            .await?
+            .into_iter()
+            .filter_map(|finding| {
+                let dismissed = dismissals.iter().any(|dismissal| {
+                    dismissal.transaction_id == finding.transaction.id
+                        && dismissal.kind == finding.kind as i32
+                });
+                (include_dismissed || !dismissed).then(|| Anomaly {
+                    kind: finding.kind as i32,
+                    reason: finding.reason,
+                    dismissed,
+                    transaction: Some(finding.transaction.into_api(&tag_names)),
+                })
+            })
+            .collect())
+    }
 
-        let id = if let Some(id) = find_first_matching_id(self.description.as_str(), tag_keywords) {
-            id
-        } else if let Some(id) = find_first_matching_id(self.partner_name.as_str(), tag_keywords) {
-            id
-        } else {
-            Thing::from(DEFAULT_TAG_ID)
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn dismiss_anomaly(
+        &self,
+        transaction_id: Thing,
+        kind: AnomalyKind,
+    ) -> Result<()> {
+        let dismissal = AnomalyDismissal {
+            id: Thing::from((
+                "anomaly_dismissal",
+                format!("{}-{}", transaction_id.id.to_raw(), kind.as_str_name()).as_str(),
+            )),
+            transaction_id,
+            kind: kind as i32,
         };
+        let id = (dismissal.id.tb.clone(), dismissal.id.id.to_raw());
+        let existing: Option<AnomalyDismissal> = self.db().select(id.clone()).await?;
+        if existing.is_none() {
+            let _result: Option<AnomalyDismissal> = self.db().create(id).content(dismissal).await?;
+        }
+        Ok(())
+    }
 
-        let leave_item = LineItemRecord {
-            description: "".to_string(),
-            amount: self.total_amount - line_amount,
-            tag_id: id,
-        };
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn save_statements(&self, statements: Vec<StatementRecord>) -> Result<()> {
+        self.db()
+            .query(
+                "FOR $statement IN $statements {
+                    UPSERT $statement.id CONTENT $statement;
+                    UPSERT type::thing('account', $statement.account_id) SET account_id = $statement.account_id, currency = $statement.currency;
+                };",
+            )
+            .bind(("statements", statements))
+            .await?
+            .check()?;
+        Ok(())
+    }
 
-        self.line_items.push(leave_item);
-        self
+    /// Manual accounts of one user, they are never shared.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_manual_accounts(&self, user_name: &str) -> Result<Vec<ManualAccount>> {
+        let result: Vec<ManualAccount> = self
+            .db()
+            .query("select * from manual_account where user_name = $user_name;")
+            .bind(("user_name", user_name.to_string()))
+            .await?
+            .take(0)?;
+        Ok(result)
     }
-}
 
-fn find_first_matching_id<'a>(
-    input: &str,
-    keyword_map: &'a HashMap<Thing, Vec<String>>,
-) -> Option<Thing> {
-    for (id, keywords) in keyword_map {
-        if keywords.iter().any(|keyword| input.contains(keyword)) {
-            return Some(id.clone());
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn save_manual_account(&self, account: ManualAccount) -> Result<()> {
+        let id = (account.id.tb.clone(), account.id.id.clone().to_raw());
+
+        let result: Option<ManualAccount> = self.db().select(id.clone()).await?;
+
+        if let Some(existing) = result {
+            if existing.user_name != account.user_name {
+                return Err(Error::PermissionDenied(
+                    "manual account belongs to another user".to_string(),
+                ));
+            }
+            let _result: Option<ManualAccount> =
+                self.db().update(id.clone()).content(account).await?;
+        } else {
+            let _result: Option<ManualAccount> =
+                self.db().create(id.clone()).content(account).await?;
         }
+
+        Ok(())
     }
-    None
-}
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-pub(crate) struct LineItemRecord {
-    description: String,
-    amount: f32,
-    tag_id: Thing,
-}
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn delete_manual_account(&self, id: Thing, user_name: &str) -> Result<()> {
+        self.db()
+            .query("DELETE $id WHERE user_name = $user_name;")
+            .bind(("id", id))
+            .bind(("user_name", user_name.to_string()))
+            .await?
+            .check()?;
+        Ok(())
+    }
 
-impl From<LineItem> for LineItemRecord {
-    fn from(value: LineItem) -> Self {
-        Self {
-            description: value.description.clone(),
-            amount: value.amount,
-            tag_id: Thing::from(("tag".to_string(), value.tag_id.clone())),
-        }
+    /// End-of-day balances per account and the net worth across all accounts in `currency`.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_balance_history(
+        &self,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        account_ids: Vec<String>,
+        currency: String,
+        access: &Access,
+    ) -> Result<BalanceHistoryResponse> {
+        let readable = access.readable_accounts();
+        let transactions = self.get_transaction_records(readable.clone()).await?;
+        let mut response = self
+            .db()
+            .query("select * from statement where account_id in $account_ids;")
+            .query("select * from account where account_id in $account_ids;")
+            .bind(("account_ids", readable))
+            .await?;
+        let statements: Vec<StatementRecord> = response.take(0)?;
+        let accounts: Vec<AccountRecord> = response.take(1)?;
+        let mut manual_accounts = self.get_manual_accounts(&access.user_name).await?;
+        let currencies: HashMap<String, String> = accounts
+            .into_iter()
+            .map(|account| (account.account_id, account.currency))
+            .collect();
+        let mut used_currencies: Vec<String> = currencies.values().cloned().collect();
+        used_currencies.extend(manual_accounts.iter().map(|a| a.currency.clone()));
+        used_currencies.push(currency.clone());
+        let rates = self.get_exchange_rates(used_currencies, None, None).await?;
+        let (send, recv) = tokio::sync::oneshot::channel();
+        rayon::spawn(move || {
+            let rates = RateTable::new(rates);
+            let mut balances = balance::end_of_day_balances(&transactions);
+            if !account_ids.is_empty() {
+                balances.retain(|account_id, _| account_ids.contains(account_id));
+            }
+            let first = balances
+                .values()
+                .filter_map(|series| series.keys().next())
+                .min()
+                .copied();
+            let from = from.or(first).unwrap_or_default();
+            let to = to.unwrap_or_else(|| chrono::Local::now().date_naive());
+
+            let accounts =
+                balance::account_histories(&balances, &currencies, &statements, from, to);
+            let result = balance::convert_balances(&balances, &currencies, &rates, &currency)
+                .and_then(|converted| {
+                    for account in manual_accounts.iter_mut() {
+                        account.balance = rates.convert(
+                            account.balance,
+                            &account.currency,
+                            &currency,
+                            account.date,
+                        )?;
+                    }
+                    let net_worth = balance::net_worth(&converted, &manual_accounts, from, to);
+                    Ok(BalanceHistoryResponse {
+                        net_worth_total: net_worth.last().map(|p| p.balance).unwrap_or_default(),
+                        accounts,
+                        net_worth,
+                    })
+                });
+            let _ = send.send(result);
+        });
+        recv.await?
     }
-}
 
-impl Into<LineItem> for LineItemRecord {
-    fn into(self) -> LineItem {
-        LineItem {
-            description: self.description,
-            amount: self.amount,
-            tag_id: self.tag_id.id.to_raw(),
-        }
+    /// Roles of `user_name` on all accounts.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_access(&self, user_name: &str) -> Result<Access> {
+        let grants: Vec<AccountGrant> = self
+            .db()
+            .query("select * from account_grant where user_name = $user_name;")
+            .bind(("user_name", user_name.to_string()))
+            .await?
+            .take(0)?;
+        Ok(Access::new(user_name.to_string(), grants))
+    }
+
+    /// Makes `user_name` owner of every account in `account_ids` that nobody has access to yet.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn claim_accounts(
+        &self,
+        account_ids: Vec<String>,
+        user_name: &str,
+    ) -> Result<()> {
+        let grants: Vec<AccountGrant> = account_ids
+            .into_iter()
+            .map(|account_id| {
+                AccountGrant::new(account_id, user_name.to_string(), AccountRole::Owner)
+            })
+            .collect();
+        self.db()
+            .query(
+                "FOR $grant IN $grants {
+                    IF count((SELECT id FROM account_grant WHERE account_id = $grant.account_id)) = 0 {
+                        CREATE $grant.id CONTENT $grant;
+                        UPSERT type::thing('account', $grant.account_id) SET account_id = $grant.account_id;
+                    };
+                };",
+            )
+            .bind(("grants", grants))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Makes `user_name` owner of every budget created before budgets had owners.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn claim_budgets(&self, user_name: &str) -> Result<()> {
+        self.db()
+            .query("UPDATE budget SET user_name = $user_name WHERE !user_name;")
+            .bind(("user_name", user_name.to_string()))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Account ids of all imported transactions.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_account_ids(&self) -> Result<Vec<String>> {
+        let account_ids: Vec<String> = self
+            .db()
+            .query("RETURN array::distinct((SELECT VALUE account_id FROM transaction));")
+            .await?
+            .take(0)?;
+        Ok(account_ids)
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_accounts(&self, access: &Access) -> Result<Vec<api::Account>> {
+        let accounts: Vec<AccountRecord> = self
+            .db()
+            .query("select * from account where account_id in $account_ids;")
+            .bind(("account_ids", access.readable_accounts()))
+            .await?
+            .take(0)?;
+        Ok(accounts
+            .into_iter()
+            .map(|account| api::Account {
+                role: access
+                    .role(&account.account_id)
+                    .map(|role| role as i32)
+                    .unwrap_or_default(),
+                household_id: account
+                    .household_id
+                    .as_ref()
+                    .map(record_key)
+                    .unwrap_or_default(),
+                account_id: account.account_id,
+                currency: account.currency,
+            })
+            .collect())
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_account(&self, account_id: &str) -> Result<Option<AccountRecord>> {
+        let account: Option<AccountRecord> = self.db().select(("account", account_id)).await?;
+        Ok(account)
+    }
+
+    /// Moves an account into a household, or out of any household if `household` is `None`.
+    /// Grants of users outside the new household are removed, except the one of `owner`.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn set_account_household(
+        &self,
+        account_id: String,
+        household: Option<Household>,
+        owner: &str,
+    ) -> Result<()> {
+        let members = household
+            .as_ref()
+            .map(|household| household.members.clone())
+            .unwrap_or_default();
+        self.db()
+            .query(
+                "BEGIN TRANSACTION;
+                UPDATE type::thing('account', $account_id) SET household_id = $household_id;
+                DELETE account_grant WHERE account_id = $account_id AND user_name != $owner AND user_name NOTINSIDE $members;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("account_id", account_id))
+            .bind(("household_id", household.map(|household| household.id)))
+            .bind(("owner", owner.to_string()))
+            .bind(("members", members))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_account_grants(&self, account_id: String) -> Result<Vec<AccountGrant>> {
+        let grants: Vec<AccountGrant> = self
+            .db()
+            .query("select * from account_grant where account_id = $account_id;")
+            .bind(("account_id", account_id))
+            .await?
+            .take(0)?;
+        Ok(grants)
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn save_account_grant(&self, grant: AccountGrant) -> Result<()> {
+        self.db()
+            .query("UPSERT $grant.id CONTENT $grant;")
+            .bind(("grant", grant))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn delete_account_grant(
+        &self,
+        account_id: String,
+        user_name: String,
+    ) -> Result<()> {
+        let grant = AccountGrant::new(account_id, user_name, AccountRole::Viewer);
+        self.db()
+            .query("DELETE $id;")
+            .bind(("id", grant.id))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn create_household(
+        &self,
+        name: String,
+        user_name: &str,
+    ) -> Result<Household> {
+        let household = Household {
+            id: Thing::from((
+                "household",
+                uuid::Uuid::new_v4().simple().to_string().as_str(),
+            )),
+            name,
+            members: vec![user_name.to_string()],
+            owner: user_name.to_string(),
+        };
+        let _result: Option<Household> = self
+            .db()
+            .create(("household", record_key(&household.id)))
+            .content(household.clone())
+            .await?;
+        Ok(household)
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_household(&self, id: Thing) -> Result<Option<Household>> {
+        let household: Option<Household> = self.db().select(("household", record_key(&id))).await?;
+        Ok(household)
+    }
+
+    /// Households `user_name` is a member of.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_households(&self, user_name: &str) -> Result<Vec<Household>> {
+        let households: Vec<Household> = self
+            .db()
+            .query("select * from household where members contains $user_name;")
+            .bind(("user_name", user_name.to_string()))
+            .await?
+            .take(0)?;
+        Ok(households)
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn add_household_member(&self, id: Thing, user_name: String) -> Result<()> {
+        self.db()
+            .query("UPDATE $id SET members = array::union(members, [$user_name]);")
+            .bind(("id", id))
+            .bind(("user_name", user_name))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Removes a member together with the roles it was given on accounts of the household.
+    /// Roles on accounts the member owns are kept.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn remove_household_member(&self, id: Thing, user_name: String) -> Result<()> {
+        self.db()
+            .query(
+                "BEGIN TRANSACTION;
+                UPDATE $id SET members -= $user_name;
+                DELETE account_grant WHERE user_name = $user_name AND role != $owner
+                    AND account_id INSIDE (SELECT VALUE account_id FROM account WHERE household_id = $id);
+                COMMIT TRANSACTION;",
+            )
+            .bind(("id", id))
+            .bind(("user_name", user_name))
+            .bind(("owner", AccountRole::Owner as i32))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_user(&self, user_name: &str) -> Result<Option<UserRecord>> {
+        let user: Option<UserRecord> = self.db().select(("user", user_name)).await?;
+        Ok(user)
+    }
+
+    /// Creates a user, fails if the user name is taken.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn create_user(&self, user: UserRecord) -> Result<()> {
+        if self.get_user(&user.user_name).await?.is_some() {
+            return Err(Error::AlreadyExists(format!(
+                "user {} already exists",
+                user.user_name
+            )));
+        }
+        let id = ("user", user.user_name.clone());
+        let _result: Option<UserRecord> = self.db().create(id).content(user).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn save_user(&self, user: UserRecord) -> Result<()> {
+        let id = ("user", user.user_name.clone());
+        let _result: Option<UserRecord> = self.db().update(id).content(user).await?;
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn revoke_token(&self, jti: String, expires_at: i64) -> Result<()> {
+        let token = RevokedToken {
+            id: Thing::from(("revoked_token", jti.as_str())),
+            jti,
+            expires_at,
+        };
+        self.db()
+            .query("UPSERT $token.id CONTENT $token;")
+            .bind(("token", token))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Ids of revoked tokens that have not expired yet, expired entries are removed.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_revoked_tokens(&self) -> Result<Vec<String>> {
+        let tokens: Vec<String> = self
+            .db()
+            .query(
+                "DELETE revoked_token WHERE expires_at < $now;
+                SELECT VALUE jti FROM revoked_token;",
+            )
+            .bind(("now", chrono::Utc::now().timestamp()))
+            .await?
+            .take(1)?;
+        Ok(tokens)
+    }
+
+    /// Start of the valid sessions of users who revoked their older tokens.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_sessions_since(&self) -> Result<HashMap<String, i64>> {
+        let users: Vec<UserRecord> = self
+            .db()
+            .query("SELECT * FROM user WHERE sessions_since > 0;")
+            .await?
+            .take(0)?;
+        Ok(users
+            .into_iter()
+            .map(|user| (user.user_name, user.sessions_since))
+            .collect())
+    }
+
+    /// Cheap round trip to check the connection.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn ping(&self) -> Result<()> {
+        self.db().query("RETURN true;").await?.check()?;
+        Ok(())
+    }
+
+    /// Number of stored transactions, tags and transactions still on the default tag.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_statistics(&self) -> Result<Statistics> {
+        let mut response = self
+            .db()
+            .query(
+                "SELECT count() AS count FROM transaction GROUP ALL;
+                SELECT count() AS count FROM tag GROUP ALL;
+                SELECT count() AS count FROM transaction WHERE line_items.tag_id CONTAINS $default GROUP ALL;",
+            )
+            .bind(("default", Thing::from(DEFAULT_TAG_ID)))
+            .await?;
+        let mut count = |index: usize| -> Result<i64> {
+            let count: Option<CountResult> = response.take(index)?;
+            Ok(count.map(|c| c.count as i64).unwrap_or_default())
+        };
+        Ok(Statistics {
+            transactions: count(0)?,
+            tags: count(1)?,
+            uncategorised: count(2)?,
+        })
+    }
+
+    /// Stores ECB reference rates, existing rates of the same day are replaced.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn import_exchange_rates(&self, rates: Vec<ExchangeRate>) -> Result<()> {
+        for chunk in rates.chunks(EXCHANGE_RATE_CHUNK_SIZE) {
+            self.db()
+                .query("FOR $rate IN $rates { UPSERT $rate.id CONTENT $rate; };")
+                .bind(("rates", chunk.to_vec()))
+                .await?
+                .check()?;
+        }
+        Ok(())
+    }
+
+    /// Rates of the given currencies (all if empty) within `from..=to`, oldest first.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_exchange_rates(
+        &self,
+        currencies: Vec<String>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Vec<ExchangeRate>> {
+        let mut conditions = Vec::new();
+        if !currencies.is_empty() {
+            conditions.push("currency in $currencies");
+        }
+        if from.is_some() {
+            conditions.push("date>=$from");
+        }
+        if to.is_some() {
+            conditions.push("date<=$to");
+        }
+        let rates: Vec<ExchangeRate> = self
+            .db()
+            .query(format!(
+                "select * from exchange_rate {}order by date;",
+                where_clause(&conditions)
+            ))
+            .bind(("currencies", currencies))
+            .bind(("from", from))
+            .bind(("to", to))
+            .await?
+            .take(0)?;
+        Ok(rates)
+    }
+
+    /// Links new transfer pairs as suggestions, pairs that were confirmed or rejected before
+    /// are kept as they are.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn detect_transfers(
+        &self,
+        window_days: i64,
+        access: &Access,
+    ) -> Result<Vec<Transfer>> {
+        let transactions = self
+            .get_transaction_records(access.editable_accounts())
+            .await?;
+        let transfers: Vec<Transfer> = self.db().select("transfer").await?;
+        let (send, recv) = tokio::sync::oneshot::channel();
+        rayon::spawn(move || {
+            let linked = transfers
+                .iter()
+                .flat_map(|t| [t.debit_id.clone(), t.credit_id.clone()])
+                .collect();
+            let pairs: Vec<Transfer> = transfer::detect(&transactions, &linked, window_days)
+                .into_iter()
+                .map(|(debit, credit)| Transfer {
+                    id: Thing::from((
+                        "transfer",
+                        format!("{}-{}", debit.id.id.to_raw(), credit.id.id.to_raw()).as_str(),
+                    )),
+                    debit_id: debit.id.clone(),
+                    credit_id: credit.id.clone(),
+                    amount: credit.total_amount,
+                    status: TransferStatus::Suggested as i32,
+                })
+                .collect();
+            let _ = send.send(pairs);
+        });
+        let pairs: Vec<Transfer> = recv.await?;
+
+        self.db()
+            .query("FOR $transfer IN $transfers { CREATE $transfer.id CONTENT $transfer; };")
+            .bind(("transfers", pairs))
+            .await?
+            .check()?;
+        self.get_transfers(access).await
+    }
+
+    /// Transfers where the caller may read both sides.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_transfers(&self, access: &Access) -> Result<Vec<Transfer>> {
+        let result: Vec<Transfer> = self
+            .db()
+            .query("select * from transfer where debit_id.account_id in $account_ids and credit_id.account_id in $account_ids;")
+            .bind(("account_ids", access.readable_accounts()))
+            .await?
+            .take(0)?;
+        Ok(result)
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_transfer(&self, id: Thing) -> Result<Option<Transfer>> {
+        let transfer: Option<Transfer> = self.db().select((id.tb.clone(), id.id.to_raw())).await?;
+        Ok(transfer)
+    }
+
+    /// Confirms a transfer pair or unlinks it by marking it rejected.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn set_transfer_status(
+        &self,
+        id: Thing,
+        status: TransferStatus,
+    ) -> Result<()> {
+        let result: Option<Transfer> = self
+            .db()
+            .query("UPDATE $id SET status = $status;")
+            .bind(("id", id.clone()))
+            .bind(("status", status as i32))
+            .await?
+            .take(0)?;
+        if result.is_none() {
+            return Err(Error::NotFound(format!(
+                "transfer {} does not exist",
+                id.id.to_raw()
+            )));
+        }
+        Ok(())
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn delete_tag(&self, id: Thing, replacement: Option<Thing>) -> Result<()> {
+        let target = replacement.unwrap_or(Thing::from(DEFAULT_TAG_ID));
+        self.move_tags(vec![id], target, false).await
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn merge_tags(&self, sources: Vec<Thing>, target: Thing) -> Result<()> {
+        self.move_tags(sources, target, true).await
+    }
+
+    /// Reassigns the line items of the source tags in all transactions to the target and
+    /// deletes the sources in a single database transaction.
+    #[instrument(level = "debug", skip_all, err)]
+    async fn move_tags(
+        &self,
+        sources: Vec<Thing>,
+        target: Thing,
+        move_keywords: bool,
+    ) -> Result<()> {
+        if sources.contains(&Thing::from(DEFAULT_TAG_ID)) {
+            return Err(Error::invalid_argument(
+                "source_ids",
+                "the default tag cannot be deleted",
+            ));
+        }
+        if sources.contains(&target) {
+            return Err(Error::invalid_argument(
+                "source_ids",
+                "a tag cannot be merged into itself",
+            ));
+        }
+
+        let tags = self.get_tags().await?;
+        let missing = std::iter::once(&target)
+            .chain(sources.iter())
+            .find(|id| !tags.iter().any(|tag| &tag.id == *id));
+        if let Some(id) = missing {
+            return Err(Error::NotFound(format!(
+                "tag {} does not exist",
+                id.id.to_raw()
+            )));
+        }
+
+        // reassigned inside the transaction, an import or SetTag in between is not overwritten
+        self.db()
+            .query(
+                "BEGIN TRANSACTION;
+                UPDATE transaction SET line_items[WHERE tag_id IN $sources].tag_id = $target
+                    WHERE line_items.tag_id ANYINSIDE $sources;
+                IF $move_keywords {
+                    UPDATE $target SET keywords = array::union(keywords, array::flatten((SELECT VALUE keywords FROM $sources)));
+                };
+                DELETE $sources;
+                COMMIT TRANSACTION;",
+            )
+            .bind(("target", target))
+            .bind(("move_keywords", move_keywords))
+            .bind(("sources", sources))
+            .await?
+            .check()?;
+        Ok(())
+    }
+
+    /// Trains the tag classifier on all categorised line items and proposes a tag for every
+    /// uncategorised transaction. Suggestions at or above `auto_apply_threshold` are saved.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn suggest_tags(
+        &self,
+        auto_apply_threshold: Option<f32>,
+        access: &Access,
+    ) -> Result<Vec<TagSuggestion>> {
+        let transactions = self
+            .get_transaction_records(access.editable_accounts())
+            .await?;
+        let (send, recv) = tokio::sync::oneshot::channel();
+        rayon::spawn(move || {
+            let default = Thing::from(DEFAULT_TAG_ID);
+            let (uncategorised, categorised): (Vec<TransactionRecord>, Vec<TransactionRecord>) =
+                transactions
+                    .into_par_iter()
+                    .partition(|t| t.is_uncategorised());
+            let samples = categorised
+                .par_iter()
+                .flat_map_iter(|t| {
+                    let features = classifier::features(t);
+                    t.line_items
+                        .iter()
+                        .filter(|item| item.tag_id != default)
+                        .map(move |item| (features.clone(), item.tag_id.clone()))
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+            let classifier = TagClassifier::train(samples);
+            let result: Vec<(TransactionRecord, Thing, f32)> = uncategorised
+                .into_par_iter()
+                .filter_map(|t| {
+                    classifier
+                        .predict(&classifier::features(&t))
+                        .map(|(tag, confidence)| (t, tag, confidence))
+                })
+                .collect();
+            let _ = send.send(result);
+        });
+
+        let mut suggestions = Vec::new();
+        for (transaction, tag, confidence) in recv.await? {
+            let applied = auto_apply_threshold.is_some_and(|threshold| confidence >= threshold);
+            let transaction_id = transaction.id.to_raw();
+            if applied {
+                self.save_transaction(transaction.apply_tag(tag.clone()))
+                    .await?;
+            }
+            suggestions.push(TagSuggestion {
+                transaction_id,
+                tag_id: tag.id.to_raw(),
+                confidence,
+                applied,
+            });
+        }
+        Ok(suggestions)
+    }
+
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_tag_balance(
+        &self,
+        positive: bool,
+        filter: TransactionFilter,
+    ) -> Result<Vec<BalanceInformation>> {
+        // converted once per tag, currency and day instead of once per line item
+        const BASE_QUERY: &str ="select math::sum(fn::convert(amount, currency, $currency, date)) as balance, name, math::sum(transaction_count) as transaction_count from (select math::sum(line_items.amount) as amount, line_items.tag_id.name as name, currency, date, count() as transaction_count from";
+        const POSITIVE: &str = "line_items.amount>0.0";
+        const NEGATIVE: &str = "line_items.amount<0.0";
+        const GROUP: &str = "group name, currency, date) group name;";
+        let mut conditions = vec![if positive { POSITIVE } else { NEGATIVE }];
+        if !filter.tag_ids.is_empty() {
+            conditions.push("line_items.tag_id in $tag_ids");
+        }
+        let result: Vec<BalanceInformation> = self
+            .db()
+            .query(format!(
+                "{}{}(select line_items, currency, date from transaction {}split line_items) {}{}",
+                LINKED_TRANSFERS,
+                BASE_QUERY,
+                where_clause(&filter.aggregate_conditions()),
+                where_clause(&conditions),
+                GROUP
+            ))
+            .bind(filter)
+            .bind(("rejected", TransferStatus::Rejected as i32))
+            .await?
+            .take(1)?;
+        Ok(result)
+    }
+
+    /// Sums income, expenses and net per period, grouped in the database.
+    #[instrument(level = "debug", skip_all, err)]
+    pub(crate) async fn get_cash_flow_series(
+        &self,
+        period: CashFlowPeriod,
+        breakdown: CashFlowBreakdown,
+        filter: TransactionFilter,
+    ) -> Result<Vec<CashFlowPoint>> {
+        let period_expression = match period {
+            CashFlowPeriod::Day => "time::group(<datetime>date, 'day')",
+            // 1970-01-01 was a thursday, shift by four days to start weeks on monday
+            CashFlowPeriod::Week => "time::floor(<datetime>date - 4d, 1w) + 4d",
+            // quarters are merged from months below
+            CashFlowPeriod::Month | CashFlowPeriod::Quarter => {
+                "time::group(<datetime>date, 'month')"
+            }
+            CashFlowPeriod::Year => "time::group(<datetime>date, 'year')",
+        };
+        let name_expression = match breakdown {
+            CashFlowBreakdown::None => "''",
+            CashFlowBreakdown::Tag => "line_items.tag_id.name",
+            CashFlowBreakdown::Partner => "partner_name",
+        };
+        let mut conditions = Vec::new();
+        if !filter.tag_ids.is_empty() {
+            conditions.push("line_items.tag_id in $tag_ids");
+        }
+        let rows: Vec<CashFlowRow> = self
+            .db()
+            .query(format!(
+                "{}select period, name, math::sum(fn::convert(income, currency, $currency, date)) as income, math::sum(fn::convert(expenses, currency, $currency, date)) as expenses from (select time::unix({}) as period, {} as name, currency, date, math::sum(math::max([line_items.amount, 0])) as income, math::sum(math::min([line_items.amount, 0])) as expenses from(select date, partner_name, line_items, currency from transaction {}split line_items) {}group period, name, currency, date) group period, name;",
+                LINKED_TRANSFERS,
+                period_expression,
+                name_expression,
+                where_clause(&filter.aggregate_conditions()),
+                where_clause(&conditions),
+            ))
+            .bind(filter)
+            .bind(("rejected", TransferStatus::Rejected as i32))
+            .await?
+            .take(1)?;
+
+        let mut points: BTreeMap<(i64, String), CashFlowPoint> = BTreeMap::new();
+        for row in rows {
+            let mut start = NaiveDate::default() + chrono::Duration::seconds(row.period);
+            if period == CashFlowPeriod::Quarter {
+                start = quarter_start(start);
+            }
+            let period_start = (start - NaiveDate::default()).num_days();
+            let name = row.name.unwrap_or_default();
+            let point = points
+                .entry((period_start, name.clone()))
+                .or_insert_with(|| CashFlowPoint {
+                    period_start,
+                    name,
+                    ..Default::default()
+                });
+            point.income += row.income as f32;
+            point.expenses += row.expenses as f32;
+            point.net = point.income + point.expenses;
+        }
+        Ok(points.into_values().collect())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CashFlowRow {
+    period: i64,
+    name: Option<String>,
+    income: f64,
+    expenses: f64,
+}
+
+/// Restricts transaction listings and aggregations, empty fields match everything.
+/// The field names are bound as query parameters.
+#[derive(Debug, Serialize, Clone, Default, PartialEq)]
+pub(crate) struct TransactionFilter {
+    pub(crate) from: Option<NaiveDate>,
+    pub(crate) to: Option<NaiveDate>,
+    pub(crate) account_ids: Vec<String>,
+    pub(crate) tag_ids: Vec<Thing>,
+    pub(crate) partner_names: Vec<String>,
+    pub(crate) min_amount: Option<f32>,
+    pub(crate) max_amount: Option<f32>,
+    pub(crate) text: String, // Lowercase search text
+    pub(crate) uncategorised_only: bool,
+    pub(crate) include_transfers: bool, // Count transfers between own accounts in aggregates
+    pub(crate) currency: String,        // Reporting currency of aggregates, euro if empty
+    pub(crate) scope: Vec<String>,      // Accounts the caller may read, nothing matches if empty
+}
+
+impl TransactionFilter {
+    /// Conditions on fields of the transaction table.
+    fn transaction_conditions(&self) -> Vec<&'static str> {
+        let mut conditions = vec!["account_id in $scope"];
+        if self.from.is_some() {
+            conditions.push("date>=$from");
+        }
+        if self.to.is_some() {
+            conditions.push("date<=$to");
+        }
+        if !self.account_ids.is_empty() {
+            conditions.push("account_id in $account_ids");
+        }
+        if !self.partner_names.is_empty() {
+            conditions.push("partner_name in $partner_names");
+        }
+        if self.min_amount.is_some() {
+            conditions.push("total_amount>=$min_amount");
+        }
+        if self.max_amount.is_some() {
+            conditions.push("total_amount<=$max_amount");
+        }
+        if !self.text.is_empty() {
+            conditions.push("(string::lowercase(description) contains $text or string::lowercase(partner_name) contains $text)");
+        }
+        if self.uncategorised_only {
+            conditions.push(
+                "line_items[where description='' and amount!=0.0].tag_id contains tag:default",
+            );
+        }
+        conditions
+    }
+}
+
+impl TransactionFilter {
+    /// Conditions for listings, which match a transaction if any line item has a tag.
+    fn listing_conditions(&self) -> Vec<&'static str> {
+        let mut conditions = self.transaction_conditions();
+        if !self.tag_ids.is_empty() {
+            conditions.push("line_items.tag_id containsany $tag_ids");
+        }
+        conditions
+    }
+
+    /// Restricts the filter to the accounts `access` may read.
+    pub(crate) fn scoped(mut self, access: &Access) -> Self {
+        self.scope = access.readable_accounts();
+        self
+    }
+
+    /// Conditions for income and expense aggregates, which leave out linked transfers
+    /// between own accounts unless requested. The query has to start with
+    /// [`LINKED_TRANSFERS`].
+    fn aggregate_conditions(&self) -> Vec<&'static str> {
+        let mut conditions = self.transaction_conditions();
+        if !self.include_transfers {
+            conditions.push("id notinside $linked_transfers");
+        }
+        conditions
+    }
+}
+
+impl From<api::BalanceRequest> for TransactionFilter {
+    fn from(value: api::BalanceRequest) -> Self {
+        Self {
+            from: value.from_date.map(days_to_date),
+            to: value.to_date.map(days_to_date),
+            account_ids: value.account_ids,
+            tag_ids: value
+                .tag_ids
+                .iter()
+                .map(|id| Thing::from(("tag", id.as_str())))
+                .collect(),
+            partner_names: value.partner_names,
+            include_transfers: value.include_transfers,
+            currency: value.currency,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<api::TransactionFilter> for TransactionFilter {
+    fn from(value: api::TransactionFilter) -> Self {
+        Self {
+            from: value.from_date.map(days_to_date),
+            to: value.to_date.map(days_to_date),
+            account_ids: value.account_ids,
+            tag_ids: value
+                .tag_ids
+                .iter()
+                .map(|id| Thing::from(("tag", id.as_str())))
+                .collect(),
+            partner_names: value.partner_names,
+            min_amount: value.min_amount,
+            max_amount: value.max_amount,
+            text: value.text.to_lowercase(),
+            uncategorised_only: value.uncategorised_only,
+            include_transfers: false,
+            currency: String::new(),
+            scope: Vec::new(),
+        }
+    }
+}
+
+/// Key of a record id without the escaping of `to_raw`.
+fn record_key(thing: &Thing) -> String {
+    match &thing.id {
+        Id::String(key) => key.clone(),
+        id => id.to_raw(),
+    }
+}
+
+/// First day of the quarter `date` falls in.
+fn quarter_start(date: NaiveDate) -> NaiveDate {
+    NaiveDate::from_ymd_opt(date.year(), date.month0() / 3 * 3 + 1, 1).unwrap_or(date)
+}
+
+fn where_clause(conditions: &[&str]) -> String {
+    if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("where {} ", conditions.join(" and "))
+    }
+}
+
+/// Converts the day count used by the API (days since 1970-01-01) into a date.
+pub(crate) fn days_to_date(days: i64) -> NaiveDate {
+    NaiveDate::default() + chrono::Duration::days(days)
+}
+
+pub(crate) fn date_to_days(date: NaiveDate) -> i64 {
+    (date - NaiveDate::default()).num_days()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct Tag {
+    pub(crate) id: Thing,
+    pub(crate) name: String,
+    pub(crate) keywords: Vec<String>,
+}
+
+impl From<api::Tag> for Tag {
+    fn from(value: api::Tag) -> Self {
+        Self {
+            id: Thing::from(("tag", value.id.as_str())),
+            name: value.name,
+            keywords: value.key_words,
+        }
+    }
+}
+
+impl From<Tag> for api::Tag {
+    fn from(value: Tag) -> Self {
+        api::Tag {
+            id: value.id.id.to_raw(),
+            name: value.name,
+            key_words: value.keywords,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct Budget {
+    pub(crate) id: Thing,
+    pub(crate) tag_id: Thing,
+    pub(crate) period: i32, // CashFlowPeriod
+    pub(crate) amount: f32,
+    pub(crate) rollover: bool,
+    pub(crate) start_date: NaiveDate,
+    #[serde(default)]
+    pub(crate) user_name: String, // Owner
+}
+
+impl From<api::Budget> for Budget {
+    fn from(value: api::Budget) -> Self {
+        let id = if value.id.is_empty() {
+            uuid::Uuid::new_v4().to_string()
+        } else {
+            value.id
+        };
+        Self {
+            id: Thing::from(("budget", id.as_str())),
+            tag_id: Thing::from(("tag", value.tag_id.as_str())),
+            period: value.period,
+            amount: value.amount,
+            rollover: value.rollover,
+            start_date: days_to_date(value.start_date),
+            user_name: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct RecurringPayment {
+    pub(crate) id: Thing,
+    pub(crate) partner_name: String,
+    pub(crate) partner_id: String,
+    pub(crate) account_id: String,
+    pub(crate) interval_days: u32,
+    pub(crate) amount: f32,
+    pub(crate) previous_amount: f32,
+    pub(crate) last_date: NaiveDate,
+    pub(crate) next_expected_date: NaiveDate,
+    pub(crate) transaction_ids: Vec<Thing>,
+    pub(crate) price_changed: bool, // Last amount differs from the one before
+    pub(crate) missed: bool,        // Next payment is overdue
+    pub(crate) is_new: bool,        // Series started within the last few intervals
+}
+
+impl From<RecurringPayment> for api::RecurringPayment {
+    fn from(value: RecurringPayment) -> Self {
+        api::RecurringPayment {
+            id: value.id.id.to_raw(),
+            partner_name: value.partner_name,
+            partner_id: value.partner_id,
+            account_id: value.account_id,
+            interval_days: value.interval_days,
+            amount: value.amount,
+            previous_amount: value.previous_amount,
+            last_date: date_to_days(value.last_date),
+            next_expected_date: date_to_days(value.next_expected_date),
+            transaction_ids: value.transaction_ids.iter().map(|id| id.to_raw()).collect(),
+            price_changed: value.price_changed,
+            missed: value.missed,
+            is_new: value.is_new,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct StatementRecord {
+    pub(crate) id: Thing,
+    pub(crate) account_id: String,
+    pub(crate) opening_date: NaiveDate,
+    pub(crate) opening_balance: f32,
+    pub(crate) closing_date: NaiveDate,
+    pub(crate) closing_balance: f32,
+    #[serde(default)]
+    pub(crate) currency: String,
+}
+
+/// Bank account of imported statements.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct AccountRecord {
+    pub(crate) id: Thing,
+    pub(crate) account_id: String, // BLZ/account number
+    #[serde(default)]
+    pub(crate) currency: String,
+    #[serde(default)]
+    pub(crate) household_id: Option<Thing>, // Household the account is shared with
+}
+
+/// Group of users that may share accounts.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct Household {
+    pub(crate) id: Thing,
+    pub(crate) name: String,
+    pub(crate) members: Vec<String>, // User names
+    pub(crate) owner: String,        // User name of the creator, who manages the members
+}
+
+impl From<Household> for api::Household {
+    fn from(value: Household) -> Self {
+        api::Household {
+            id: record_key(&value.id),
+            name: value.name,
+            members: value.members,
+            owner: value.owner,
+        }
+    }
+}
+
+/// Role of a user on one account.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct AccountGrant {
+    pub(crate) id: Thing, // account_grant:⟨account id|user name⟩
+    pub(crate) account_id: String,
+    pub(crate) user_name: String,
+    pub(crate) role: i32, // AccountRole
+}
+
+impl AccountGrant {
+    pub(crate) fn new(account_id: String, user_name: String, role: AccountRole) -> Self {
+        Self {
+            id: Thing::from((
+                "account_grant",
+                format!("{}|{}", account_id, user_name).as_str(),
+            )),
+            account_id,
+            user_name,
+            role: role as i32,
+        }
+    }
+
+    pub(crate) fn role(&self) -> AccountRole {
+        AccountRole::try_from(self.role).unwrap_or(AccountRole::Viewer)
+    }
+}
+
+impl From<AccountGrant> for api::AccountGrant {
+    fn from(value: AccountGrant) -> Self {
+        api::AccountGrant {
+            account_id: value.account_id,
+            user_name: value.user_name,
+            role: value.role,
+        }
+    }
+}
+
+/// ECB reference rate of a currency on one day.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct ExchangeRate {
+    pub(crate) id: Thing,
+    pub(crate) currency: String,
+    pub(crate) date: NaiveDate,
+    pub(crate) rate: f32, // Units of the currency per euro
+}
+
+impl From<ExchangeRate> for api::ExchangeRate {
+    fn from(value: ExchangeRate) -> Self {
+        api::ExchangeRate {
+            currency: value.currency,
+            date: date_to_days(value.date),
+            rate: value.rate,
+        }
+    }
+}
+
+/// Asset or liability whose balance is maintained by hand, e.g. a depot or a loan.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct ManualAccount {
+    pub(crate) id: Thing,
+    pub(crate) name: String,
+    pub(crate) kind: i32, // AccountKind
+    pub(crate) balance: f32,
+    pub(crate) date: NaiveDate, // Day the balance is valid from
+    #[serde(default)]
+    pub(crate) currency: String,
+    #[serde(default)]
+    pub(crate) user_name: String, // Owner
+}
+
+impl From<api::ManualAccount> for ManualAccount {
+    fn from(value: api::ManualAccount) -> Self {
+        let id = if value.id.is_empty() {
+            uuid::Uuid::new_v4().to_string()
+        } else {
+            value.id
+        };
+        Self {
+            id: Thing::from(("manual_account", id.as_str())),
+            name: value.name,
+            kind: value.kind,
+            balance: value.balance,
+            date: days_to_date(value.date),
+            currency: value.currency,
+            user_name: String::new(),
+        }
+    }
+}
+
+/// Debit and credit of a transfer between two own accounts.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct Transfer {
+    pub(crate) id: Thing,
+    pub(crate) debit_id: Thing,
+    pub(crate) credit_id: Thing,
+    pub(crate) amount: f32,
+    pub(crate) status: i32, // TransferStatus
+}
+
+impl From<Transfer> for api::Transfer {
+    fn from(value: Transfer) -> Self {
+        api::Transfer {
+            id: value.id.id.to_raw(),
+            debit_id: value.debit_id.to_raw(),
+            credit_id: value.credit_id.to_raw(),
+            amount: value.amount,
+            status: value.status,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct UserRecord {
+    pub(crate) id: Thing, // user:⟨user name⟩
+    pub(crate) user_name: String,
+    pub(crate) password_hash: String, // argon2 PHC string
+    pub(crate) is_admin: bool,
+    #[serde(default)]
+    pub(crate) sessions_since: i64, // Unix timestamp in milliseconds, tokens issued before are revoked
+}
+
+impl From<UserRecord> for api::User {
+    fn from(value: UserRecord) -> Self {
+        api::User {
+            user_name: value.user_name,
+            is_admin: value.is_admin,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct RevokedToken {
+    id: Thing,
+    jti: String,
+    expires_at: i64, // Unix timestamp, the entry can be dropped afterwards
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct AnomalyDismissal {
+    id: Thing,
+    transaction_id: Thing,
+    kind: i32, // AnomalyKind
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct QueryResult {
+    id: Thing,
+    date: NaiveDate,
+    total_amount: f64,
+    balance_after_transaction: f64,
+    currency: Option<String>,
+    original_currency: Option<String>,
+    original_amount: Option<f64>,
+    partner_name: String,
+    description: String,
+    tags: Vec<String>,
+}
+
+impl From<QueryResult> for Transaction {
+    fn from(res: QueryResult) -> Self {
+        Transaction {
+            id: res.id.to_raw(),
+            date: (res.date - NaiveDate::default()).num_days(),
+            total_amount: res.total_amount as f32,
+            balance_after_transaction: res.balance_after_transaction as f32,
+            currency: res.currency.unwrap_or_default(),
+            original_currency: res.original_currency.unwrap_or_default(),
+            original_amount: res.original_amount.unwrap_or_default() as f32,
+            partner_name: res.partner_name,
+            description: res.description,
+            tags: res.tags,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResult {
+    id: Thing,
+    date: NaiveDate,
+    total_amount: f64,
+    balance_after_transaction: f64,
+    currency: Option<String>,
+    original_currency: Option<String>,
+    original_amount: Option<f64>,
+    partner_name: String,
+    description: String,
+    tags: Vec<String>,
+    description_highlight: Option<String>,
+    partner_highlight: Option<String>,
+    score: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct CountResult {
+    count: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Statistics {
+    pub(crate) transactions: i64,
+    pub(crate) tags: i64,
+    pub(crate) uncategorised: i64,
+}
+
+/// Joins sort value and transaction id of the last listed transaction into a cursor.
+fn format_cursor(key: &str, id: &Thing) -> String {
+    format!("{}|{}", key, record_key(id))
+}
+
+/// Splits a listing cursor into sort value and transaction id.
+fn parse_cursor(cursor: &str) -> Result<(String, Thing)> {
+    let (key, id) = cursor
+        .split_once('|')
+        .ok_or_else(|| Error::invalid_argument(CURSOR_FIELD, "missing separator"))?;
+    Ok((key.to_string(), Thing::from(("transaction", id))))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct TransactionRecord {
+    pub(crate) id: Thing,            // Unique identifier for the transaction
+    pub(crate) account_id: String,   // ID of the bank account where the transaction occurred
+    pub(crate) date: NaiveDate,      // Date of the transaction (e.g., "2024-09-04")
+    pub(crate) total_amount: f32,    // Total amount of the transaction
+    pub(crate) partner_name: String, // Reference ID to the transaction partner
+    #[serde(default)]
+    pub(crate) partner_id: String, // Mandate reference or IBAN of the transaction partner
+    pub(crate) line_items: Vec<LineItemRecord>, // List of line items within the transaction
+    pub(crate) description: String,  // Description or memo of the transaction
+    pub(crate) balance_after_transaction: f32, // Account balance after the transaction
+    #[serde(default)]
+    pub(crate) position: u32, // Position within the bank statement
+    #[serde(default)]
+    pub(crate) currency: String, // Currency of the account, euro if empty
+    #[serde(default)]
+    pub(crate) original_currency: String, // Currency of a payment abroad
+    #[serde(default)]
+    pub(crate) original_amount: f32, // Amount in the original currency
+}
+
+impl Default for TransactionRecord {
+    fn default() -> Self {
+        Self {
+            id: Thing::from(("transaction", "tmp")),
+            account_id: Default::default(),
+            date: Default::default(),
+            total_amount: Default::default(),
+            partner_name: Default::default(),
+            partner_id: Default::default(),
+            line_items: Default::default(),
+            description: Default::default(),
+            balance_after_transaction: Default::default(),
+            position: Default::default(),
+            currency: Default::default(),
+            original_currency: Default::default(),
+            original_amount: Default::default(),
+        }
+    }
+}
+
+impl TransactionRecord {
+    fn update_tags(mut self, tag_keywords: &HashMap<Thing, Vec<String>>) -> Self {
+        let suggested = self
+            .line_items
+            .iter()
+            .find(|item| item.description.is_empty() && item.suggested)
+            .map(|item| item.tag_id.clone());
+        self.line_items.retain(|item| !item.description.is_empty());
+
+        let line_amount: f32 = self.line_items.iter().map(|item| item.amount).sum();
+
+        // keywords take precedence over an accepted suggestion
+        let (id, suggested) = if let Some(id) =
+            find_first_matching_id(self.description.as_str(), tag_keywords)
+        {
+            (id, false)
+        } else if let Some(id) = find_first_matching_id(self.partner_name.as_str(), tag_keywords) {
+            (id, false)
+        } else if let Some(id) = suggested {
+            (id, true)
+        } else {
+            (Thing::from(DEFAULT_TAG_ID), false)
+        };
+
+        let leave_item = LineItemRecord {
+            description: "".to_string(),
+            amount: self.total_amount - line_amount,
+            tag_id: id,
+            suggested,
+        };
+
+        self.line_items.push(leave_item);
+        self
+    }
+
+    /// Mandate reference or IBAN of the partner, the name if neither is known.
+    pub(crate) fn partner_key(&self) -> &str {
+        if self.partner_id.is_empty() {
+            &self.partner_name
+        } else {
+            &self.partner_id
+        }
+    }
+
+    fn matches_keywords(&self, keywords: &[String]) -> bool {
+        keywords.iter().any(|keyword| {
+            self.description.contains(keyword) || self.partner_name.contains(keyword)
+        })
+    }
+
+    /// Tag of the remaining amount that is not covered by manual line items.
+    fn remaining_tag(&self) -> Option<Thing> {
+        self.line_items
+            .iter()
+            .find(|item| item.description.is_empty())
+            .map(|item| item.tag_id.clone())
+    }
+
+    pub(crate) fn into_api(self, tag_names: &HashMap<Thing, String>) -> Transaction {
+        Transaction {
+            id: self.id.to_raw(),
+            date: (self.date - NaiveDate::default()).num_days(),
+            total_amount: self.total_amount,
+            balance_after_transaction: self.balance_after_transaction,
+            currency: self.currency,
+            original_currency: self.original_currency,
+            original_amount: self.original_amount,
+            partner_name: self.partner_name,
+            description: self.description,
+            tags: self
+                .line_items
+                .iter()
+                .filter_map(|item| tag_names.get(&item.tag_id).cloned())
+                .collect(),
+        }
+    }
+
+    /// A transaction is uncategorised if its remaining amount is still booked on the default tag.
+    fn is_uncategorised(&self) -> bool {
+        self.line_items.iter().any(|item| {
+            item.description.is_empty()
+                && item.amount != 0.0
+                && item.tag_id == Thing::from(DEFAULT_TAG_ID)
+        })
+    }
+
+    /// Books the remaining amount on `tag` and marks it as suggested, so it survives
+    /// the next keyword update unless a keyword matches.
+    fn apply_tag(mut self, tag: Thing) -> Self {
+        for item in self.line_items.iter_mut() {
+            if item.description.is_empty() {
+                item.tag_id = tag.clone();
+                item.suggested = true;
+            }
+        }
+        self
+    }
+}
+
+fn find_first_matching_id(input: &str, keyword_map: &HashMap<Thing, Vec<String>>) -> Option<Thing> {
+    for (id, keywords) in keyword_map {
+        if keywords.iter().any(|keyword| input.contains(keyword)) {
+            return Some(id.clone());
+        }
+    }
+    None
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub(crate) struct LineItemRecord {
+    pub(crate) description: String,
+    pub(crate) amount: f32,
+    pub(crate) tag_id: Thing,
+    #[serde(default)]
+    pub(crate) suggested: bool, // Tag of the remaining amount was applied by SuggestTags
+}
+
+impl From<LineItem> for LineItemRecord {
+    fn from(value: LineItem) -> Self {
+        Self {
+            description: value.description.clone(),
+            amount: value.amount,
+            tag_id: Thing::from(("tag".to_string(), value.tag_id.clone())),
+            suggested: false,
+        }
+    }
+}
+
+impl From<LineItemRecord> for LineItem {
+    fn from(value: LineItemRecord) -> Self {
+        LineItem {
+            description: value.description,
+            amount: value.amount,
+            tag_id: value.tag_id.id.to_raw(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn database() -> Database {
+        Database::memory().await
+    }
+
+    fn tag(id: &str, keywords: &[&str]) -> Tag {
+        Tag {
+            id: Thing::from(("tag", id)),
+            name: id.to_string(),
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+        }
+    }
+
+    fn transaction(id: &str, account_id: &str, tag_id: &str) -> TransactionRecord {
+        TransactionRecord {
+            id: Thing::from(("transaction", id)),
+            account_id: account_id.to_string(),
+            date: NaiveDate::from_ymd_opt(2024, 9, 4).unwrap(),
+            total_amount: -20.0,
+            line_items: vec![LineItemRecord {
+                description: String::new(),
+                amount: -20.0,
+                tag_id: Thing::from(("tag", tag_id)),
+                suggested: false,
+            }],
+            ..Default::default()
+        }
+    }
+
+    async fn tag_of(db: &Database, id: &str) -> Thing {
+        let transaction = db
+            .get_transaction(Thing::from(("transaction", id)))
+            .await
+            .unwrap()
+            .unwrap();
+        transaction.line_items[0].tag_id.clone()
+    }
+
+    #[test]
+    fn test_apply_tag() {
+        let mut rewe = transaction("rewe", "giro", "default");
+        rewe.description = "REWE SAGT DANKE".to_string();
+        let applied = rewe.apply_tag(Thing::from(("tag", "groceries")));
+        assert_eq!(applied.line_items[0].description, "");
+        assert!(!applied.is_uncategorised());
+
+        let kept = applied.clone().update_tags(&HashMap::new());
+        assert_eq!(kept.line_items, applied.line_items);
+        let keywords = HashMap::from([(Thing::from(("tag", "food")), vec!["REWE".to_string()])]);
+        let matched = applied.update_tags(&keywords);
+        assert_eq!(matched.line_items[0].tag_id, Thing::from(("tag", "food")));
+        assert!(!matched.line_items[0].suggested);
+    }
+
+    #[tokio::test]
+    async fn test_merge_tags() {
+        let db = database().await;
+        db.save_tag(tag("food", &["REWE"])).await.unwrap();
+        db.save_tag(tag("groceries", &["ALDI"])).await.unwrap();
+        db.save_transaction(transaction("rewe", "giro", "food"))
+            .await
+            .unwrap();
+
+        db.merge_tags(
+            vec![Thing::from(("tag", "food"))],
+            Thing::from(("tag", "groceries")),
+        )
+        .await
+        .unwrap();
+        assert_eq!(tag_of(&db, "rewe").await, Thing::from(("tag", "groceries")));
+        let tags = db.get_tags().await.unwrap();
+        assert!(!tags.iter().any(|t| t.id == Thing::from(("tag", "food"))));
+        let groceries = tags
+            .into_iter()
+            .find(|t| t.id == Thing::from(("tag", "groceries")))
+            .unwrap();
+        assert_eq!(groceries.keywords.len(), 2);
+        assert!(groceries.keywords.contains(&"REWE".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_delete_tag() {
+        let db = database().await;
+        db.save_tag(tag("food", &["REWE"])).await.unwrap();
+        db.save_transaction(transaction("rewe", "giro", "food"))
+            .await
+            .unwrap();
+        db.save_transaction(transaction("aldi", "savings", "food"))
+            .await
+            .unwrap();
+
+        let missing = db
+            .delete_tag(Thing::from(("tag", "fuel")), None)
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        // tags are shared, the line items of every account move to the default tag
+        db.delete_tag(Thing::from(("tag", "food")), None)
+            .await
+            .unwrap();
+        assert_eq!(tag_of(&db, "rewe").await, Thing::from(DEFAULT_TAG_ID));
+        assert_eq!(tag_of(&db, "aldi").await, Thing::from(DEFAULT_TAG_ID));
+        let tags = db.get_tags().await.unwrap();
+        assert_eq!(tags.len(), 1);
+        assert!(tags[0].keywords.is_empty());
+    }
+
+    #[test]
+    fn test_where_clause() {
+        assert_eq!(where_clause(&[]), "");
+        assert_eq!(
+            where_clause(&["date>=$from", "date<=$to"]),
+            "where date>=$from and date<=$to "
+        );
+    }
+
+    #[test]
+    fn test_filter_conditions() {
+        let filter = TransactionFilter::default();
+        assert_eq!(
+            filter.transaction_conditions(),
+            vec!["account_id in $scope"]
+        );
+        assert_eq!(filter.aggregate_conditions().len(), 2);
+
+        let filter = TransactionFilter {
+            from: NaiveDate::from_ymd_opt(2024, 1, 1),
+            account_ids: vec!["giro".to_string()],
+            partner_names: vec!["REWE".to_string()],
+            max_amount: Some(0.0),
+            include_transfers: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            filter.aggregate_conditions(),
+            vec![
+                "account_id in $scope",
+                "date>=$from",
+                "account_id in $account_ids",
+                "partner_name in $partner_names",
+                "total_amount<=$max_amount",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_filtered_partner_balance() {
+        let db = database().await;
+        let mut records = vec![
+            transaction("old", "giro", "default"),
+            transaction("rewe", "giro", "default"),
+            transaction("aldi", "giro", "default"),
+            transaction("other", "savings", "default"),
+        ];
+        records[0].date = NaiveDate::from_ymd_opt(2023, 12, 31).unwrap();
+        for record in records.iter_mut() {
+            record.partner_name = if record.id.id.to_raw() == "aldi" {
+                "ALDI".to_string()
+            } else {
+                "REWE".to_string()
+            };
+        }
+        for record in records {
+            db.save_transaction(record).await.unwrap();
+        }
+
+        let filter = TransactionFilter {
+            from: NaiveDate::from_ymd_opt(2024, 1, 1),
+            partner_names: vec!["REWE".to_string()],
+            scope: vec!["giro".to_string()],
+            ..Default::default()
+        };
+        let balances = db.get_partner_balance(false, filter).await.unwrap();
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].name, "REWE");
+        assert_eq!(balances[0].transaction_count, 1);
+        assert_eq!(balances[0].balance, -20.0);
+
+        let unscoped = TransactionFilter::default();
+        assert!(db
+            .get_partner_balance(false, unscoped)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_linked_transfers_left_out() {
+        let db = database().await;
+        let mut credit = transaction("in", "savings", "default");
+        credit.total_amount = 20.0;
+        db.save_transaction(transaction("out", "giro", "default"))
+            .await
+            .unwrap();
+        db.save_transaction(credit).await.unwrap();
+        db.db()
+            .query("CREATE transfer:pair SET debit_id = transaction:out, credit_id = transaction:in, amount = 20, status = $status;")
+            .bind(("status", TransferStatus::Confirmed as i32))
+            .await
+            .unwrap()
+            .check()
+            .unwrap();
+
+        let mut filter = TransactionFilter {
+            scope: vec!["giro".to_string(), "savings".to_string()],
+            ..Default::default()
+        };
+        let balances = db.get_partner_balance(false, filter.clone()).await.unwrap();
+        assert!(balances.is_empty());
+        filter.include_transfers = true;
+        let balances = db.get_partner_balance(false, filter).await.unwrap();
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].balance, -20.0);
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn test_quarter_start() {
+        assert_eq!(quarter_start(date(2024, 1, 1)), date(2024, 1, 1));
+        assert_eq!(quarter_start(date(2024, 3, 31)), date(2024, 1, 1));
+        assert_eq!(quarter_start(date(2024, 8, 15)), date(2024, 7, 1));
+        assert_eq!(quarter_start(date(2024, 12, 31)), date(2024, 10, 1));
+    }
+
+    async fn cash_flow(db: &Database, period: CashFlowPeriod) -> Vec<(NaiveDate, f32)> {
+        let filter = TransactionFilter {
+            scope: vec!["giro".to_string()],
+            ..Default::default()
+        };
+        db.get_cash_flow_series(period, CashFlowBreakdown::None, filter)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|point| (days_to_date(point.period_start), point.expenses))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_cash_flow_periods() {
+        let db = database().await;
+        let dates = [
+            date(2024, 3, 31), // sunday
+            date(2024, 4, 1),  // monday
+            date(2024, 4, 7),  // sunday
+            date(2024, 4, 8),  // monday
+        ];
+        for (i, date) in dates.into_iter().enumerate() {
+            let mut record = transaction(&format!("t{}", i), "giro", "default");
+            record.date = date;
+            db.save_transaction(record).await.unwrap();
+        }
+
+        assert_eq!(
+            cash_flow(&db, CashFlowPeriod::Week).await,
+            vec![
+                (date(2024, 3, 25), -20.0),
+                (date(2024, 4, 1), -40.0),
+                (date(2024, 4, 8), -20.0),
+            ]
+        );
+        assert_eq!(
+            cash_flow(&db, CashFlowPeriod::Quarter).await,
+            vec![(date(2024, 1, 1), -20.0), (date(2024, 4, 1), -60.0)]
+        );
+    }
+
+    #[test]
+    fn test_cursor() {
+        // keys that to_raw would escape must survive the round trip
+        let id = Thing::from(("transaction", "0c4f-9a"));
+        let cursor = format_cursor("-12.5", &id);
+        assert_eq!(parse_cursor(&cursor).unwrap(), ("-12.5".to_string(), id));
+        assert_eq!(
+            parse_cursor("2024-09-04").unwrap_err(),
+            Error::invalid_argument(CURSOR_FIELD, "missing separator")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_list_transactions_pages() {
+        let db = database().await;
+        for (i, day) in [4, 2, 3, 3, 1].into_iter().enumerate() {
+            let mut record = transaction(&format!("t{}", i), "giro", "default");
+            record.date = date(2024, 9, day);
+            db.save_transaction(record).await.unwrap();
+        }
+        let filter = TransactionFilter {
+            scope: vec!["giro".to_string()],
+            ..Default::default()
+        };
+
+        let mut ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = db
+                .list_transactions(filter.clone(), TransactionSort::DateDesc, Some(2), cursor)
+                .await
+                .unwrap();
+            assert_eq!(page.total_count, 5);
+            assert!(page.transactions.len() <= 2);
+            ids.extend(page.transactions.into_iter().map(|t| t.id));
+            if page.next_cursor.is_empty() {
+                break;
+            }
+            cursor = Some(page.next_cursor);
+        }
+        assert_eq!(
+            ids,
+            vec![
+                "transaction:t0",
+                "transaction:t3",
+                "transaction:t2",
+                "transaction:t1",
+                "transaction:t4",
+            ]
+        );
+    }
+
+    fn access(account_id: &str) -> Access {
+        Access::new(
+            "anna".to_string(),
+            vec![AccountGrant::new(
+                account_id.to_string(),
+                "anna".to_string(),
+                AccountRole::Owner,
+            )],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_search_transactions() {
+        let db = database().await;
+        let mut rewe = transaction("rewe", "giro", "default");
+        rewe.partner_name = "REWE Markt".to_string();
+        rewe.description = "Einkäufe für die Woche".to_string();
+        let mut bakery = transaction("bakery", "giro", "default");
+        bakery.partner_name = "Bäckerei Müller".to_string();
+        bakery.description = "Kartenzahlung".to_string();
+        db.save_transaction(rewe).await.unwrap();
+        db.save_transaction(bakery).await.unwrap();
+        // without documents that do not match, BM25 scores every match with 0
+        for (id, partner_name) in [("rent", "Hausverwaltung"), ("salary", "Arbeitgeber")] {
+            let mut other = transaction(id, "giro", "default");
+            other.partner_name = partner_name.to_string();
+            other.description = "Dauerauftrag".to_string();
+            db.save_transaction(other).await.unwrap();
+        }
+
+        // stemmed, only the description matches
+        let matches = db
+            .search_transactions("einkauf".to_string(), 10, &access("giro"))
+            .await
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0].score > 0.0);
+        assert_eq!(
+            matches[0].description_highlight,
+            "<b>Einkäufe</b> für die Woche"
+        );
+        assert_eq!(matches[0].partner_highlight, "REWE Markt");
+
+        // folded to ascii, only the partner matches
+        let matches = db
+            .search_transactions("backerei".to_string(), 10, &access("giro"))
+            .await
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+        assert!(matches[0].score > 0.0);
+        assert_eq!(matches[0].partner_highlight, "<b>Bäckerei</b> Müller");
+        assert_eq!(matches[0].description_highlight, "Kartenzahlung");
+
+        let matches = db
+            .search_transactions("einkauf".to_string(), 10, &access("savings"))
+            .await
+            .unwrap();
+        assert!(matches.is_empty());
+    }
+
+    fn budget(tag_id: &str) -> Budget {
+        Budget {
+            id: Thing::from(("budget", "food")),
+            tag_id: Thing::from(("tag", tag_id)),
+            period: CashFlowPeriod::Month as i32,
+            amount: 300.0,
+            rollover: false,
+            start_date: date(2024, 1, 1),
+            user_name: "anna".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_budget() {
+        let db = database().await;
+        let missing = db.save_budget(budget("food")).await.unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+        assert!(db.get_budgets("anna").await.unwrap().is_empty());
+
+        db.save_tag(tag("food", &[])).await.unwrap();
+        db.save_budget(budget("food")).await.unwrap();
+        assert_eq!(db.get_budgets("anna").await.unwrap(), vec![budget("food")]);
+        assert!(db.get_budgets("bert").await.unwrap().is_empty());
+
+        let foreign = Budget {
+            user_name: "bert".to_string(),
+            ..budget("food")
+        };
+        let denied = db.save_budget(foreign).await.unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        db.delete_budget(budget("food").id, "bert").await.unwrap();
+        assert_eq!(db.get_budgets("anna").await.unwrap(), vec![budget("food")]);
     }
 }
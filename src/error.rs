@@ -0,0 +1,221 @@
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+use tonic::{Code, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+
+/// Clients should wait this long before retrying while SurrealDB is unreachable, about the
+/// time until the connection check notices and reconnects.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Failures of the server, each one maps to the gRPC status code a client can act on.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Error {
+    /// A request field has an invalid value, sent as field violation of a `BadRequest`.
+    InvalidArgument {
+        field: String,
+        description: String,
+    },
+    NotFound(String),
+    AlreadyExists(String),
+    /// The caller has no valid session token.
+    Unauthenticated(String),
+    PermissionDenied(String),
+    /// The request is fine, but the stored data does not allow it, e.g. a missing exchange rate.
+    FailedPrecondition(String),
+    /// SurrealDB cannot be reached, sent with a `RetryInfo`.
+    Unavailable(String),
+    Internal(String),
+}
+
+impl Error {
+    pub(crate) fn invalid_argument(field: &str, description: impl Display) -> Self {
+        Error::InvalidArgument {
+            field: field.to_string(),
+            description: description.to_string(),
+        }
+    }
+
+    pub(crate) fn code(&self) -> Code {
+        match self {
+            Error::InvalidArgument { .. } => Code::InvalidArgument,
+            Error::NotFound(_) => Code::NotFound,
+            Error::AlreadyExists(_) => Code::AlreadyExists,
+            Error::Unauthenticated(_) => Code::Unauthenticated,
+            Error::PermissionDenied(_) => Code::PermissionDenied,
+            Error::FailedPrecondition(_) => Code::FailedPrecondition,
+            Error::Unavailable(_) => Code::Unavailable,
+            Error::Internal(_) => Code::Internal,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidArgument { field, description } if field.is_empty() => {
+                f.write_str(description)
+            }
+            Error::InvalidArgument { field, description } => {
+                write!(f, "{}: {}", field, description)
+            }
+            Error::NotFound(message)
+            | Error::AlreadyExists(message)
+            | Error::Unauthenticated(message)
+            | Error::PermissionDenied(message)
+            | Error::FailedPrecondition(message)
+            | Error::Unavailable(message)
+            | Error::Internal(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for Status {
+    fn from(error: Error) -> Self {
+        let code = error.code();
+        let message = error.to_string();
+        match error {
+            Error::InvalidArgument { field, description } => Status::with_error_details(
+                code,
+                message,
+                ErrorDetails::with_bad_request_violation(field, description),
+            ),
+            Error::Unavailable(_) => Status::with_error_details(
+                code,
+                message,
+                ErrorDetails::with_retry_info(Some(RETRY_DELAY)),
+            ),
+            _ => Status::new(code, message),
+        }
+    }
+}
+
+/// Statuses of helpers shared with the handlers, like the session interceptor, keep their code.
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        let message = status.message().to_string();
+        match status.code() {
+            Code::InvalidArgument => {
+                let field = status
+                    .get_details_bad_request()
+                    .and_then(|bad_request| bad_request.field_violations.into_iter().next())
+                    .map(|violation| violation.field)
+                    .unwrap_or_default();
+                let description = message
+                    .strip_prefix(&format!("{}: ", field))
+                    .unwrap_or(&message)
+                    .to_string();
+                Error::InvalidArgument { field, description }
+            }
+            Code::NotFound => Error::NotFound(message),
+            Code::AlreadyExists => Error::AlreadyExists(message),
+            Code::Unauthenticated => Error::Unauthenticated(message),
+            Code::PermissionDenied => Error::PermissionDenied(message),
+            Code::FailedPrecondition => Error::FailedPrecondition(message),
+            Code::Unavailable => Error::Unavailable(message),
+            _ => Error::Internal(message),
+        }
+    }
+}
+
+impl From<surrealdb::Error> for Error {
+    fn from(error: surrealdb::Error) -> Self {
+        use surrealdb::error::{Api, Db};
+        let message = error.to_string();
+        match error {
+            surrealdb::Error::Api(Api::ConnectionUninitialised | Api::Ws(_)) => {
+                Error::Unavailable(message)
+            }
+            surrealdb::Error::Db(Db::RecordExists { .. } | Db::IndexExists { .. }) => {
+                Error::AlreadyExists(message)
+            }
+            // THROW is used for data that is missing, like exchange rates
+            surrealdb::Error::Db(Db::Thrown(thrown)) => Error::FailedPrecondition(thrown),
+            _ => Error::Internal(message),
+        }
+    }
+}
+
+/// Failures the client cannot do anything about.
+macro_rules! internal_from {
+    ($($source:ty),* $(,)?) => {
+        $(
+            impl From<$source> for Error {
+                fn from(error: $source) -> Self {
+                    Error::Internal(error.to_string())
+                }
+            }
+        )*
+    };
+}
+
+internal_from!(
+    std::io::Error,
+    tokio::sync::oneshot::error::RecvError,
+    tracing_subscriber::filter::ParseError,
+    tracing_subscriber::util::TryInitError,
+    axum::http::header::InvalidHeaderName,
+    axum::http::header::InvalidHeaderValue,
+    axum::http::method::InvalidMethod,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_details() {
+        let status = Status::from(Error::invalid_argument("cursor", "unknown sort value"));
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "cursor: unknown sort value");
+        let violations = status.get_details_bad_request().unwrap().field_violations;
+        assert_eq!(violations[0].field, "cursor");
+        assert_eq!(violations[0].description, "unknown sort value");
+        assert_eq!(
+            Error::from(status),
+            Error::invalid_argument("cursor", "unknown sort value")
+        );
+
+        let status = Status::from(Error::Unavailable("connection lost".to_string()));
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(
+            status.get_details_retry_info().unwrap().retry_delay,
+            Some(RETRY_DELAY)
+        );
+
+        let status = Status::from(Error::NotFound("tag food does not exist".to_string()));
+        assert_eq!(status.code(), Code::NotFound);
+        assert!(status.get_details_bad_request().is_none());
+        assert_eq!(
+            Error::from(Status::permission_denied("read only")),
+            Error::PermissionDenied("read only".to_string())
+        );
+        let status = Status::from(Error::from(Status::unauthenticated("missing bearer token")));
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert_eq!(status.message(), "missing bearer token");
+    }
+
+    #[test]
+    fn test_surrealdb_errors() {
+        use surrealdb::error::Db;
+        let thing = surrealdb::sql::Thing::from(("tag", "food"));
+        assert_eq!(
+            Error::from(surrealdb::Error::Db(Db::RecordExists { thing })).code(),
+            Code::AlreadyExists
+        );
+        assert_eq!(
+            Error::from(surrealdb::Error::Db(Db::Thrown(
+                "no exchange rate".to_string()
+            ))),
+            Error::FailedPrecondition("no exchange rate".to_string())
+        );
+        assert_eq!(
+            Error::from(surrealdb::Error::Db(Db::Unreachable("x".to_string()))).code(),
+            Code::Internal
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+
+use chrono::NaiveDate;
+use lazy_static::lazy_static;
+use regex::Regex;
+use surrealdb::sql::Thing;
+
+use crate::database::ExchangeRate;
+use crate::error::{Error, Result};
+use crate::metrics;
+
+/// Currency the ECB reference rates are quoted against, amounts without currency are in euro.
+pub(crate) const BASE_CURRENCY: &str = "EUR";
+/// Request field holding the rate file, named in the violations of invalid input.
+const INPUT_FIELD: &str = "data";
+
+lazy_static! {
+    static ref CUBE: Regex = Regex::new(
+        r#"<Cube\s+(?:time=["'](\d{4}-\d{2}-\d{2})["']|currency=["']([A-Z]{3})["']\s+rate=["']([0-9.]+)["'])"#
+    )
+    .unwrap();
+}
+
+/// Reads the euro reference rates published by the ECB, either as XML (`eurofxref-daily.xml`,
+/// `eurofxref-hist.xml`) or as CSV (`eurofxref.csv`, `eurofxref-hist.csv`).
+pub(crate) fn parse_ecb(input: &str) -> Result<Vec<ExchangeRate>> {
+    let started = Instant::now();
+    let rates = if input.trim_start().starts_with('<') {
+        parse_xml(input)
+    } else {
+        parse_csv(input)?
+    };
+    metrics::observe_parse("ecb", started.elapsed());
+    if rates.is_empty() {
+        return Err(Error::invalid_argument(
+            INPUT_FIELD,
+            "no exchange rates found",
+        ));
+    }
+    Ok(rates)
+}
+
+fn parse_xml(input: &str) -> Vec<ExchangeRate> {
+    let mut rates = Vec::new();
+    let mut date = None;
+    for cube in CUBE.captures_iter(input) {
+        if let Some(time) = cube.get(1) {
+            date = NaiveDate::parse_from_str(time.as_str(), "%Y-%m-%d").ok();
+        } else if let (Some(date), Some(rate)) = (date, cube[3].parse().ok()) {
+            rates.push(exchange_rate(&cube[2], date, rate));
+        }
+    }
+    rates
+}
+
+fn parse_csv(input: &str) -> Result<Vec<ExchangeRate>> {
+    let mut lines = input.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<&str> = lines
+        .next()
+        .ok_or_else(|| Error::invalid_argument(INPUT_FIELD, "empty exchange rate file"))?
+        .split(',')
+        .map(str::trim)
+        .collect();
+
+    let mut rates = Vec::new();
+    for line in lines {
+        let columns: Vec<&str> = line.split(',').map(str::trim).collect();
+        let Some(day) = columns.first() else {
+            continue;
+        };
+        let date = NaiveDate::parse_from_str(day, "%Y-%m-%d")
+            .or_else(|_| NaiveDate::parse_from_str(day, "%d %B %Y"))
+            .map_err(|e| Error::invalid_argument(INPUT_FIELD, format!("date {}: {}", day, e)))?;
+        for (currency, value) in header.iter().zip(columns.iter()).skip(1) {
+            // currencies without quote on that day are marked N/A
+            if let Ok(rate) = value.parse() {
+                rates.push(exchange_rate(currency, date, rate));
+            }
+        }
+    }
+    Ok(rates)
+}
+
+fn exchange_rate(currency: &str, date: NaiveDate, rate: f32) -> ExchangeRate {
+    ExchangeRate {
+        id: Thing::from(("exchange_rate", format!("{}-{}", currency, date).as_str())),
+        currency: currency.to_string(),
+        date,
+        rate,
+    }
+}
+
+/// Exchange rates per currency and day for conversions outside the database.
+#[derive(Debug, Default)]
+pub(crate) struct RateTable {
+    rates: HashMap<String, BTreeMap<NaiveDate, f32>>,
+}
+
+impl RateTable {
+    pub(crate) fn new(rates: Vec<ExchangeRate>) -> Self {
+        let mut table = Self::default();
+        for rate in rates {
+            table
+                .rates
+                .entry(rate.currency)
+                .or_default()
+                .insert(rate.date, rate.rate);
+        }
+        table
+    }
+
+    /// Units of `currency` per euro on `date`. Uses the last rate before the date and the
+    /// first known rate for dates before the oldest quote.
+    fn per_euro(&self, currency: &str, date: NaiveDate) -> Option<f32> {
+        if currency.is_empty() || currency == BASE_CURRENCY {
+            return Some(1.0);
+        }
+        let series = self.rates.get(currency)?;
+        series
+            .range(..=date)
+            .next_back()
+            .or_else(|| series.range(date..).next())
+            .map(|(_, rate)| *rate)
+    }
+
+    pub(crate) fn convert(
+        &self,
+        amount: f32,
+        from: &str,
+        to: &str,
+        date: NaiveDate,
+    ) -> Result<f32> {
+        let missing = |currency: &str| {
+            Error::FailedPrecondition(format!("no exchange rate for {}", currency))
+        };
+        let from_rate = self.per_euro(from, date).ok_or_else(|| missing(from))?;
+        let to_rate = self.per_euro(to, date).ok_or_else(|| missing(to))?;
+        Ok(amount / from_rate * to_rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_convert() {
+        let csv = "Date, USD, JPY, CYP, \n17 October 2024, 1.5, 160.25, N/A, \n";
+        let xml = "<gesmes:Envelope><Cube><Cube time='2024-10-16'>
+            <Cube currency='USD' rate='1.25'/><Cube currency='JPY' rate='162.5'/>
+            </Cube></Cube></gesmes:Envelope>";
+
+        let mut rates = parse_ecb(csv).unwrap();
+        assert_eq!(rates.len(), 2);
+        rates.extend(parse_ecb(xml).unwrap());
+        assert_eq!(rates.len(), 4);
+        assert_eq!(rates[2].currency, "USD");
+        assert_eq!(
+            rates[2].date,
+            NaiveDate::from_ymd_opt(2024, 10, 16).unwrap()
+        );
+
+        let table = RateTable::new(rates);
+        let date = |d| NaiveDate::from_ymd_opt(2024, 10, d).unwrap();
+        assert_eq!(table.convert(125.0, "USD", "EUR", date(16)), Ok(100.0));
+        assert_eq!(table.convert(100.0, "EUR", "JPY", date(20)), Ok(16025.0));
+        assert_eq!(table.convert(100.0, "", "USD", date(1)), Ok(125.0));
+        assert!(table.convert(1.0, "CHF", "EUR", date(16)).is_err());
+    }
+}
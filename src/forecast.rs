@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{Duration, NaiveDate};
+use surrealdb::sql::Thing;
+
+use crate::api::{AccountForecast, CashFlowPeriod, ForecastPoint};
+use crate::database::{date_to_days, Budget, RecurringPayment, TransactionRecord};
+use crate::recurring;
+
+/// Number of days before today used for the average spending per tag. Accounts with a
+/// shorter history are averaged over the days since their first booking.
+const HISTORY_DAYS: i64 = 180;
+
+/// Projects the balance of every account day by day from the last known balance.
+///
+/// Each day adds the recurring payments expected on that day and the historical daily average
+/// per tag of all other transactions. Tags with a budget use the budget instead of their history.
+pub(crate) fn forecast(
+    transactions: &[TransactionRecord],
+    recurring_payments: &[RecurringPayment],
+    budgets: &[Budget],
+    today: NaiveDate,
+    days: i64,
+    low_balance_threshold: f32,
+) -> Vec<AccountForecast> {
+    let mut start_balances: HashMap<&str, &TransactionRecord> = HashMap::new();
+    for transaction in transactions {
+        let latest = start_balances
+            .entry(transaction.account_id.as_str())
+            .or_insert(transaction);
+        // bookings of the same day follow their order in the statement
+        if (transaction.date, transaction.position) > (latest.date, latest.position) {
+            *latest = transaction;
+        }
+    }
+
+    let rates = daily_rates(transactions, recurring_payments, budgets, today);
+
+    let mut result: Vec<AccountForecast> = start_balances
+        .into_iter()
+        .map(|(account_id, latest)| {
+            let rate: f32 = rates
+                .iter()
+                .filter(|((account, _), _)| *account == account_id)
+                .map(|(_, rate)| rate)
+                .sum();
+            let mut expected: HashMap<NaiveDate, f32> = HashMap::new();
+            for payment in recurring_payments
+                .iter()
+                .filter(|payment| payment.account_id == account_id && payment.interval_days > 0)
+            {
+                let mut date = payment.next_expected_date;
+                while date <= today + Duration::days(days) {
+                    if date > today {
+                        *expected.entry(date).or_default() += payment.amount;
+                    }
+                    date = recurring::next_date(date, payment.interval_days as i64);
+                }
+            }
+
+            let mut forecast = AccountForecast {
+                account_id: account_id.to_string(),
+                start_balance: latest.balance_after_transaction,
+                minimum_balance: latest.balance_after_transaction,
+                ..Default::default()
+            };
+            let mut balance = latest.balance_after_transaction;
+            for day in 1..=days {
+                let date = today + Duration::days(day);
+                balance += rate + expected.get(&date).copied().unwrap_or_default();
+                forecast.minimum_balance = forecast.minimum_balance.min(balance);
+                if balance < low_balance_threshold && forecast.below_threshold_date.is_none() {
+                    forecast.below_threshold_date = Some(date_to_days(date));
+                }
+                forecast.points.push(ForecastPoint {
+                    date: date_to_days(date),
+                    balance,
+                });
+            }
+            forecast
+        })
+        .collect();
+    result.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+    result
+}
+
+/// Average daily amount per account and tag, excluding recurring payments.
+fn daily_rates<'a>(
+    transactions: &'a [TransactionRecord],
+    recurring_payments: &[RecurringPayment],
+    budgets: &[Budget],
+    today: NaiveDate,
+) -> HashMap<(&'a str, Thing), f32> {
+    let recurring_ids: HashSet<&Thing> = recurring_payments
+        .iter()
+        .flat_map(|payment| payment.transaction_ids.iter())
+        .collect();
+    let history_start = today - Duration::days(HISTORY_DAYS);
+    let history: Vec<&TransactionRecord> = transactions
+        .iter()
+        .filter(|t| t.date > history_start && t.date <= today)
+        .collect();
+
+    let mut first_bookings: HashMap<&str, NaiveDate> = HashMap::new();
+    for transaction in &history {
+        first_bookings
+            .entry(transaction.account_id.as_str())
+            .and_modify(|first| *first = (*first).min(transaction.date))
+            .or_insert(transaction.date);
+    }
+
+    let mut rates: HashMap<(&str, Thing), f32> = HashMap::new();
+    for transaction in history.iter().filter(|t| !recurring_ids.contains(&t.id)) {
+        let account_id = transaction.account_id.as_str();
+        let covered_days = (today - first_bookings[account_id]).num_days() + 1;
+        for item in &transaction.line_items {
+            *rates.entry((account_id, item.tag_id.clone())).or_default() +=
+                item.amount / covered_days as f32;
+        }
+    }
+
+    // budgets are spread over the accounts by their share of the tag's history
+    let all_accounts: HashSet<&str> = transactions.iter().map(|t| t.account_id.as_str()).collect();
+    for budget in budgets {
+        let budget_rate = -budget.amount / period_days(budget.period());
+        let mut accounts: Vec<(&str, f32)> = rates
+            .iter()
+            .filter(|((_, tag), _)| *tag == budget.tag_id)
+            .map(|((account, _), rate)| (*account, *rate))
+            .collect();
+        if accounts.is_empty() {
+            accounts = all_accounts.iter().map(|account| (*account, 0.0)).collect();
+        }
+        let total: f32 = accounts.iter().map(|(_, rate)| rate).sum();
+        let count = accounts.len() as f32;
+        for (account, rate) in accounts {
+            let share = if total == 0.0 {
+                1.0 / count
+            } else {
+                rate / total
+            };
+            rates.insert((account, budget.tag_id.clone()), budget_rate * share);
+        }
+    }
+    rates
+}
+
+fn period_days(period: CashFlowPeriod) -> f32 {
+    match period {
+        CashFlowPeriod::Day => 1.0,
+        CashFlowPeriod::Week => 7.0,
+        CashFlowPeriod::Month => 30.44,
+        CashFlowPeriod::Quarter => 91.31,
+        CashFlowPeriod::Year => 365.25,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::LineItemRecord;
+
+    #[test]
+    fn test_forecast_warns_below_threshold() {
+        let today = NaiveDate::from_ymd_opt(2024, 7, 16).unwrap();
+        let transactions = vec![TransactionRecord {
+            account_id: "15091704/3000185000".to_string(),
+            date: today,
+            total_amount: -100.0,
+            balance_after_transaction: 500.0,
+            ..Default::default()
+        }];
+        let rent = RecurringPayment {
+            id: Thing::from(("recurring_payment", "rent")),
+            partner_name: "Vermieter".to_string(),
+            partner_id: String::new(),
+            account_id: "15091704/3000185000".to_string(),
+            interval_days: 30,
+            amount: -450.0,
+            previous_amount: -450.0,
+            last_date: NaiveDate::from_ymd_opt(2024, 7, 1).unwrap(),
+            next_expected_date: NaiveDate::from_ymd_opt(2024, 8, 1).unwrap(),
+            transaction_ids: Vec::new(),
+            price_changed: false,
+            missed: false,
+            is_new: false,
+        };
+
+        let result = forecast(&transactions, &[rent], &[], today, 31, 100.0);
+        assert_eq!(result.len(), 1);
+        let account = &result[0];
+        assert_eq!(account.points.len(), 31);
+        assert_eq!(account.minimum_balance, 50.0);
+        assert_eq!(
+            account.below_threshold_date,
+            Some(date_to_days(NaiveDate::from_ymd_opt(2024, 8, 1).unwrap()))
+        );
+    }
+
+    #[test]
+    fn test_forecast_starts_from_last_booking() {
+        let today = NaiveDate::from_ymd_opt(2024, 7, 16).unwrap();
+        let booking = |position: u32, balance: f32| TransactionRecord {
+            account_id: "15091704/3000185000".to_string(),
+            date: today,
+            position,
+            balance_after_transaction: balance,
+            ..Default::default()
+        };
+        let mut transactions = vec![booking(0, 600.0), booking(2, 450.0), booking(1, 500.0)];
+        for _ in 0..transactions.len() {
+            let result = forecast(&transactions, &[], &[], today, 1, 0.0);
+            assert_eq!(result[0].start_balance, 450.0);
+            transactions.rotate_left(1);
+        }
+    }
+
+    #[test]
+    fn test_daily_rates_cover_the_history() {
+        let today = NaiveDate::from_ymd_opt(2024, 7, 16).unwrap();
+        let food = Thing::from(("tag", "food"));
+        let booking = |days_ago: i64| TransactionRecord {
+            account_id: "15091704/3000185000".to_string(),
+            date: today - Duration::days(days_ago),
+            line_items: vec![LineItemRecord {
+                description: String::new(),
+                amount: -50.0,
+                tag_id: food.clone(),
+                suggested: false,
+            }],
+            ..Default::default()
+        };
+
+        // a new account is averaged over its ten days, not the whole history window
+        let transactions = vec![booking(9), booking(0)];
+        let rates = daily_rates(&transactions, &[], &[], today);
+        assert_eq!(rates[&("15091704/3000185000", food)], -10.0);
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+use tracing::warn;
+
+use crate::database::Database;
+
+/// A database that does not answer within this time counts as unavailable.
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+/// Interval of the database check behind the gRPC health status.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// gRPC services whose status follows the database, the empty name stands for the server.
+const SERVICES: [&str; 3] = ["", "money_view.MoneyView", "money_view.Auth"];
+
+/// Liveness and readiness of the server for `/healthz`, `/readyz` and `grpc.health.v1`.
+///
+/// The server is ready once startup (schema, admin account, folder imports) is done and
+/// as long as SurrealDB answers. It stops being ready when shutdown begins.
+#[derive(Debug, Clone)]
+pub(crate) struct Health {
+    db: Database,
+    ready: Arc<AtomicBool>,
+    reporter: HealthReporter,
+}
+
+impl Health {
+    pub(crate) fn new(db: Database, reporter: HealthReporter) -> Self {
+        Self {
+            db,
+            ready: Arc::new(AtomicBool::new(false)),
+            reporter,
+        }
+    }
+
+    pub(crate) async fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+        self.report(ready).await;
+    }
+
+    /// Keeps the gRPC health status in line with the database connection.
+    pub(crate) fn watch(&self) {
+        let health = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let serving = health.is_ready() && health.database_available().await;
+                health.report(serving).await;
+            }
+        });
+    }
+
+    async fn report(&self, serving: bool) {
+        let status = if serving {
+            ServingStatus::Serving
+        } else {
+            ServingStatus::NotServing
+        };
+        let mut reporter = self.reporter.clone();
+        for service in SERVICES {
+            reporter.set_service_status(service, status).await;
+        }
+    }
+
+    fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    async fn database_available(&self) -> bool {
+        match tokio::time::timeout(PING_TIMEOUT, self.db.ping()).await {
+            Ok(Ok(())) => true,
+            Ok(Err(e)) => {
+                warn!(error = %e, "database check failed");
+                false
+            }
+            Err(_) => {
+                warn!("database check timed out");
+                false
+            }
+        }
+    }
+
+    /// `GET /healthz`: the process answers. SurrealDB is left out, an outage there must not
+    /// get the server restarted, it reconnects on its own.
+    pub(crate) async fn live(self) -> impl IntoResponse {
+        (StatusCode::OK, "ok")
+    }
+
+    /// `GET /readyz`: startup is done, the server is not shutting down and SurrealDB answers.
+    pub(crate) async fn ready(self) -> impl IntoResponse {
+        if !self.is_ready() {
+            (StatusCode::SERVICE_UNAVAILABLE, "not ready")
+        } else if !self.database_available().await {
+            (StatusCode::SERVICE_UNAVAILABLE, "database unavailable")
+        } else {
+            (StatusCode::OK, "ready")
+        }
+    }
+}
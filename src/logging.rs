@@ -0,0 +1,74 @@
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::{LogConfig, LogFormat};
+use crate::error::Result;
+use crate::metrics::{QueryLayer, DATABASE_TARGET};
+
+/// Whether [`sensitive`] values are hidden, switched on until the configuration says otherwise.
+static REDACT: AtomicBool = AtomicBool::new(true);
+
+/// Installs the global subscriber. `RUST_LOG` takes precedence over the configured level.
+/// Closed spans are logged with their duration, so slow requests and queries show up.
+/// The database spans also feed the query metrics, independent of the log level.
+pub(crate) fn init(config: &LogConfig) -> Result<()> {
+    REDACT.store(config.redact, Ordering::Relaxed);
+    let filter =
+        EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new(&config.level))?;
+    let output = tracing_subscriber::fmt::layer().with_span_events(FmtSpan::CLOSE);
+    let output = match config.format {
+        LogFormat::Text => output.boxed(),
+        LogFormat::Pretty => output.pretty().boxed(),
+        LogFormat::Json => output
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(output.with_filter(filter))
+        .with(QueryLayer.with_filter(Targets::new().with_target(DATABASE_TARGET, Level::DEBUG)))
+        .try_init()?;
+    Ok(())
+}
+
+/// Marks a log field that may contain personal data like IBANs or amounts,
+/// e.g. `debug!(iban = %sensitive(&partner_id))`.
+pub(crate) fn sensitive<T: Display>(value: T) -> Sensitive<T> {
+    Sensitive(value)
+}
+
+pub(crate) struct Sensitive<T>(T);
+
+impl<T: Display> Display for Sensitive<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if REDACT.load(Ordering::Relaxed) {
+            f.write_str("[redacted]")
+        } else {
+            self.0.fmt(f)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sensitive() {
+        REDACT.store(true, Ordering::Relaxed);
+        assert_eq!(
+            sensitive("DE12150917040003001234").to_string(),
+            "[redacted]"
+        );
+        REDACT.store(false, Ordering::Relaxed);
+        assert_eq!(sensitive(-48.32).to_string(), "-48.32");
+    }
+}
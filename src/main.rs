@@ -1,34 +1,180 @@
+// Record ids (`Thing`) are used as map keys throughout. Clippy counts them as mutable
+// keys because an id can nest a `Value`, whose regex variant caches internally; ids are
+// never changed while they are keys.
+#![allow(clippy::mutable_key_type)]
+
+use access::Access;
+use anomaly::AnomalySettings;
+use api::auth_server::Auth;
+use auth::Sessions;
 use axum::http::StatusCode;
-use axum::routing::get_service;
-use database::Database;
+use axum::routing::{get, get_service};
+use config::Config;
+use database::{days_to_date, AccountGrant, Database, Household, TransactionFilter, UserRecord};
 use dotenvy::dotenv;
+use error::Error;
+use health::Health;
+use itertools::Itertools;
+use logging::sensitive;
 use parser::parse;
+use rest::Rest;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tonic::service::interceptor::InterceptedService;
 use tonic::service::Routes;
-use std::env;
-use std::path::PathBuf;
-use tower_http::cors::{Any, CorsLayer};
 use tower_http::services::ServeDir;
+use tower_http::trace::TraceLayer;
+use tracing::{error, field, info, info_span, instrument, warn, Span};
 pub(crate) mod generated {
     pub(crate) mod money_view;
 }
 
 use api::money_view_server::MoneyView;
 use api::{
-    BalanceResponse, Empty, Tag, TagResponse, TextRequest, TransactionPartnerResponse,
-    TransactionResponse,
+    AccountGrantRequest, AccountGrantResponse, AccountHouseholdRequest, AccountResponse,
+    AccountRole, AnomalyRequest, AnomalyResponse, BalanceHistoryRequest, BalanceHistoryResponse,
+    BalanceRequest, BalanceResponse, Budget, BudgetResponse, BudgetStatusRequest,
+    BudgetStatusResponse, CashFlowRequest, CashFlowResponse, ChangePasswordRequest,
+    CreateUserRequest, DeleteAccountGrantRequest, DeleteBudgetRequest, DeleteManualAccountRequest,
+    DeleteTagRequest, DetectTransfersRequest, DismissAnomalyRequest, Empty, ExchangeRateRequest,
+    ExchangeRateResponse, ExportTransactionsRequest, ForecastRequest, ForecastResponse,
+    HouseholdMemberRequest, HouseholdResponse, ImportExchangeRatesRequest,
+    ImportExchangeRatesResponse, ListTransactionsRequest, ListTransactionsResponse, LoginRequest,
+    LoginResponse, ManualAccount, ManualAccountResponse, MergeTagsRequest,
+    RecurringPaymentResponse, SearchTransactionsRequest, SearchTransactionsResponse,
+    SetTransferStatusRequest, SuggestTagsRequest, Tag, TagResponse, TagSuggestionResponse,
+    TagTestResponse, TextRequest, Transaction, TransactionPartnerResponse, TransactionResponse,
+    TransferResponse, User,
 };
+use surrealdb::sql::Thing;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 
+pub(crate) mod access;
+pub(crate) mod anomaly;
 pub(crate) mod api;
+pub(crate) mod auth;
+pub(crate) mod balance;
+pub(crate) mod budget;
+pub(crate) mod classifier;
+pub(crate) mod config;
 pub(crate) mod database;
+pub(crate) mod error;
+pub(crate) mod exchange;
+pub(crate) mod forecast;
+pub(crate) mod health;
+pub(crate) mod logging;
+pub(crate) mod metrics;
+pub(crate) mod recurring;
+pub(crate) mod rest;
+pub(crate) mod server;
+pub(crate) mod transfer;
+
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 1000;
+const EXPORT_CHUNK_SIZE: u32 = 500;
+const DEFAULT_SEARCH_LIMIT: u32 = 50;
+const DEFAULT_FORECAST_MONTHS: u32 = 3;
+const MAX_FORECAST_MONTHS: u32 = 24;
+const DEFAULT_DUPLICATE_WINDOW_DAYS: u32 = 3;
+const DEFAULT_TRANSFER_WINDOW_DAYS: u32 = 5;
+/// Subfolder of an import folder that receives the imported files.
+const IMPORTED_FOLDER: &str = "imported";
 
 #[derive(Debug)]
 struct MoneyViewServer {
     db: Database,
+    jobs: TaskTracker, // Tasks that outlive a request, awaited on shutdown
+    sessions: Sessions,
+}
+
+impl MoneyViewServer {
+    /// Accounts and roles of the authenticated caller.
+    async fn access<T: Sync>(&self, request: &Request<T>) -> Result<Access, Status> {
+        let claims = auth::claims(request)?;
+        self.db
+            .get_access(&claims.sub)
+            .await
+            .map_err(to_tonic_error)
+    }
+
+    /// Fails unless the caller is an admin, needed for data all users share like tags and
+    /// exchange rates.
+    fn require_admin<T>(&self, request: &Request<T>, action: &str) -> Result<(), Error> {
+        if !auth::claims(request)?.admin {
+            return Err(Error::PermissionDenied(format!(
+                "only admins can {}",
+                action
+            )));
+        }
+        Ok(())
+    }
+
+    /// Household the caller is a member of.
+    async fn household(&self, id: &str, user_name: &str) -> Result<Household, Status> {
+        self.db
+            .get_household(Thing::from(("household", id)))
+            .await
+            .map_err(to_tonic_error)?
+            .filter(|household| household.members.iter().any(|m| m == user_name))
+            .ok_or_else(|| Status::not_found(format!("household {} not found", id)))
+    }
+
+    /// Household the caller owns, only the owner may change the members.
+    async fn owned_household(&self, id: &str, user_name: &str) -> Result<Household, Status> {
+        let household = self.household(id, user_name).await?;
+        if household.owner != user_name {
+            return Err(Status::permission_denied(
+                "only the owner can change the members of a household",
+            ));
+        }
+        Ok(household)
+    }
+}
+
+#[derive(Debug)]
+struct AuthServer {
+    db: Database,
+    sessions: Sessions,
+}
+
+#[tonic::async_trait]
+impl Auth for AuthServer {
+    async fn login(
+        &self,
+        request: Request<LoginRequest>,
+    ) -> Result<Response<LoginResponse>, Status> {
+        let request = request.into_inner();
+        let invalid = || Status::unauthenticated("invalid user name or password");
+        let Some(user) = self
+            .db
+            .get_user(&request.user_name)
+            .await
+            .map_err(to_tonic_error)?
+        else {
+            // as slow as a wrong password, the answer time must not reveal user names
+            auth::reject_password(request.password).await;
+            return Err(invalid());
+        };
+        if !auth::verify_password(request.password, user.password_hash.clone()).await {
+            return Err(invalid());
+        }
+        let (token, claims) = self.sessions.issue(&user)?;
+
+        Ok(Response::new(LoginResponse {
+            token,
+            expires_at: claims.exp,
+        }))
+    }
 }
 
 #[tonic::async_trait]
 impl MoneyView for MoneyViewServer {
+    type ExportTransactionsStream = ReceiverStream<Result<Transaction, Status>>;
+
     // ... existing code ...
 
     async fn get_tags(&self, _request: Request<Empty>) -> Result<Response<TagResponse>, Status> {
@@ -41,10 +187,11 @@ impl MoneyView for MoneyViewServer {
             .map(|t| t.into())
             .collect();
 
-        Ok(Response::new(TagResponse { tags: tags })) // Placeholder return
+        Ok(Response::new(TagResponse { tags })) // Placeholder return
     }
 
     async fn set_tag(&self, request: Request<Tag>) -> Result<Response<Empty>, Status> {
+        self.require_admin(&request, "change tags")?;
         self.db
             .save_tag(request.into_inner().into())
             .await
@@ -53,43 +200,820 @@ impl MoneyView for MoneyViewServer {
 
         Ok(Response::new(Empty {})) // Placeholder return
     }
+
+    async fn test_tag(&self, request: Request<Tag>) -> Result<Response<TagTestResponse>, Status> {
+        let access = self.access(&request).await?;
+        let response = self
+            .db
+            .test_tag(request.into_inner().into(), &access)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_budgets(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<BudgetResponse>, Status> {
+        let claims = auth::claims(&request)?;
+        let budgets = self
+            .db
+            .get_budgets(&claims.sub)
+            .await
+            .map_err(to_tonic_error)?
+            .into_iter()
+            .map(|b| b.into())
+            .collect();
+
+        Ok(Response::new(BudgetResponse { budgets }))
+    }
+
+    async fn set_budget(&self, request: Request<Budget>) -> Result<Response<Empty>, Status> {
+        let claims = auth::claims(&request)?;
+        let budget = request.into_inner();
+        if budget.amount <= 0.0 {
+            return Err(Error::invalid_argument("amount", "budget amount must be positive").into());
+        }
+        if budget.tag_id.is_empty() {
+            return Err(Error::invalid_argument("tag_id", "budget needs a tag").into());
+        }
+        let mut budget: database::Budget = budget.into();
+        budget.user_name = claims.sub;
+        self.db.save_budget(budget).await.map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn delete_budget(
+        &self,
+        request: Request<DeleteBudgetRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let claims = auth::claims(&request)?;
+        self.db
+            .delete_budget(
+                Thing::from(("budget", request.into_inner().id.as_str())),
+                &claims.sub,
+            )
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn get_budget_status(
+        &self,
+        request: Request<BudgetStatusRequest>,
+    ) -> Result<Response<BudgetStatusResponse>, Status> {
+        let access = self.access(&request).await?;
+        let date = request
+            .into_inner()
+            .date
+            .map(days_to_date)
+            .unwrap_or_else(|| chrono::Local::now().date_naive());
+        let statuses = self
+            .db
+            .get_budget_status(date, &access)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(BudgetStatusResponse { statuses }))
+    }
+
+    async fn detect_recurring_payments(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<RecurringPaymentResponse>, Status> {
+        let access = self.access(&request).await?;
+        let payments = self
+            .db
+            .detect_recurring_payments(chrono::Local::now().date_naive(), &access)
+            .await
+            .map_err(to_tonic_error)?
+            .into_iter()
+            .map(|p| p.into())
+            .collect();
+
+        Ok(Response::new(RecurringPaymentResponse { payments }))
+    }
+
+    async fn get_recurring_payments(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<RecurringPaymentResponse>, Status> {
+        let access = self.access(&request).await?;
+        let payments = self
+            .db
+            .get_recurring_payments(&access)
+            .await
+            .map_err(to_tonic_error)?
+            .into_iter()
+            .map(|p| p.into())
+            .collect();
+
+        Ok(Response::new(RecurringPaymentResponse { payments }))
+    }
+
+    async fn get_forecast(
+        &self,
+        request: Request<ForecastRequest>,
+    ) -> Result<Response<ForecastResponse>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        let months = if request.months == 0 {
+            DEFAULT_FORECAST_MONTHS
+        } else {
+            request.months.min(MAX_FORECAST_MONTHS)
+        };
+        let accounts = self
+            .db
+            .get_forecast(
+                chrono::Local::now().date_naive(),
+                months,
+                request.low_balance_threshold,
+                &access,
+            )
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(ForecastResponse { accounts }))
+    }
+
+    async fn get_anomalies(
+        &self,
+        request: Request<AnomalyRequest>,
+    ) -> Result<Response<AnomalyResponse>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        let duplicate_window_days = if request.duplicate_window_days == 0 {
+            DEFAULT_DUPLICATE_WINDOW_DAYS
+        } else {
+            request.duplicate_window_days
+        };
+        let settings = AnomalySettings {
+            duplicate_window_days: duplicate_window_days as i64,
+            new_partner_threshold: request.new_partner_threshold,
+        };
+        let anomalies = self
+            .db
+            .get_anomalies(settings, request.include_dismissed, &access)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(AnomalyResponse { anomalies }))
+    }
+
+    async fn dismiss_anomaly(
+        &self,
+        request: Request<DismissAnomalyRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        let transaction_id = transaction_thing(&request.transaction_id);
+        let transaction = self
+            .db
+            .get_transaction(transaction_id.clone())
+            .await
+            .map_err(to_tonic_error)?
+            .filter(|t| access.can(&t.account_id, AccountRole::Viewer))
+            .ok_or_else(|| Status::not_found("transaction not found"))?;
+        access.require(&transaction.account_id, AccountRole::Editor)?;
+        self.db
+            .dismiss_anomaly(transaction_id, request.kind())
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn get_balance_history(
+        &self,
+        request: Request<BalanceHistoryRequest>,
+    ) -> Result<Response<BalanceHistoryResponse>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        let response = self
+            .db
+            .get_balance_history(
+                request.from_date.map(days_to_date),
+                request.to_date.map(days_to_date),
+                request.account_ids,
+                request.currency,
+                &access,
+            )
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_manual_accounts(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<ManualAccountResponse>, Status> {
+        let claims = auth::claims(&request)?;
+        let accounts = self
+            .db
+            .get_manual_accounts(&claims.sub)
+            .await
+            .map_err(to_tonic_error)?
+            .into_iter()
+            .map(|a| a.into())
+            .collect();
+
+        Ok(Response::new(ManualAccountResponse { accounts }))
+    }
+
+    async fn set_manual_account(
+        &self,
+        request: Request<ManualAccount>,
+    ) -> Result<Response<Empty>, Status> {
+        let claims = auth::claims(&request)?;
+        let mut account: database::ManualAccount = request.into_inner().into();
+        account.user_name = claims.sub;
+        self.db
+            .save_manual_account(account)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn delete_manual_account(
+        &self,
+        request: Request<DeleteManualAccountRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let claims = auth::claims(&request)?;
+        self.db
+            .delete_manual_account(
+                Thing::from(("manual_account", request.into_inner().id.as_str())),
+                &claims.sub,
+            )
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn import_exchange_rates(
+        &self,
+        request: Request<ImportExchangeRatesRequest>,
+    ) -> Result<Response<ImportExchangeRatesResponse>, Status> {
+        self.require_admin(&request, "import exchange rates")?;
+        let result: Result<u32, Status> = async {
+            let rates = exchange::parse_ecb(&request.into_inner().data).map_err(to_tonic_error)?;
+            let count = rates.len() as u32;
+            self.db
+                .import_exchange_rates(rates)
+                .await
+                .map_err(to_tonic_error)?;
+            Ok(count)
+        }
+        .await;
+        metrics::record_import("ecb", result.is_ok());
+
+        Ok(Response::new(ImportExchangeRatesResponse {
+            count: result?,
+        }))
+    }
+
+    async fn get_exchange_rates(
+        &self,
+        request: Request<ExchangeRateRequest>,
+    ) -> Result<Response<ExchangeRateResponse>, Status> {
+        let request = request.into_inner();
+        let currencies = if request.currency.is_empty() {
+            Vec::new()
+        } else {
+            vec![request.currency]
+        };
+        let rates = self
+            .db
+            .get_exchange_rates(
+                currencies,
+                request.from_date.map(days_to_date),
+                request.to_date.map(days_to_date),
+            )
+            .await
+            .map_err(to_tonic_error)?
+            .into_iter()
+            .map(|r| r.into())
+            .collect();
+
+        Ok(Response::new(ExchangeRateResponse { rates }))
+    }
+
+    async fn create_household(
+        &self,
+        request: Request<api::Household>,
+    ) -> Result<Response<api::Household>, Status> {
+        let claims = auth::claims(&request)?;
+        let name = request.into_inner().name;
+        if name.trim().is_empty() {
+            return Err(Error::invalid_argument("name", "household name must not be empty").into());
+        }
+        let household = self
+            .db
+            .create_household(name, &claims.sub)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(household.into()))
+    }
+
+    async fn get_households(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<HouseholdResponse>, Status> {
+        let claims = auth::claims(&request)?;
+        let households = self
+            .db
+            .get_households(&claims.sub)
+            .await
+            .map_err(to_tonic_error)?
+            .into_iter()
+            .map(|h| h.into())
+            .collect();
+
+        Ok(Response::new(HouseholdResponse { households }))
+    }
+
+    async fn add_household_member(
+        &self,
+        request: Request<HouseholdMemberRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let claims = auth::claims(&request)?;
+        let request = request.into_inner();
+        let household = self
+            .owned_household(&request.household_id, &claims.sub)
+            .await?;
+        self.db
+            .get_user(&request.user_name)
+            .await
+            .map_err(to_tonic_error)?
+            .ok_or_else(|| Status::not_found(format!("user {} not found", request.user_name)))?;
+        self.db
+            .add_household_member(household.id, request.user_name)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn remove_household_member(
+        &self,
+        request: Request<HouseholdMemberRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let claims = auth::claims(&request)?;
+        let request = request.into_inner();
+        let household = self
+            .owned_household(&request.household_id, &claims.sub)
+            .await?;
+        if request.user_name == household.owner {
+            return Err(Error::invalid_argument(
+                "user_name",
+                "the owner cannot be removed from the household",
+            )
+            .into());
+        }
+        self.db
+            .remove_household_member(household.id, request.user_name)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn get_accounts(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<AccountResponse>, Status> {
+        let access = self.access(&request).await?;
+        let accounts = self
+            .db
+            .get_accounts(&access)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(AccountResponse { accounts }))
+    }
+
+    async fn set_account_household(
+        &self,
+        request: Request<AccountHouseholdRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        access.require(&request.account_id, AccountRole::Owner)?;
+        let household = if request.household_id.is_empty() {
+            None
+        } else {
+            Some(
+                self.household(&request.household_id, &access.user_name)
+                    .await?,
+            )
+        };
+        self.db
+            .set_account_household(request.account_id, household, &access.user_name)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn get_account_grants(
+        &self,
+        request: Request<AccountGrantRequest>,
+    ) -> Result<Response<AccountGrantResponse>, Status> {
+        let access = self.access(&request).await?;
+        let account_id = request.into_inner().account_id;
+        access.require(&account_id, AccountRole::Viewer)?;
+        let grants = self
+            .db
+            .get_account_grants(account_id)
+            .await
+            .map_err(to_tonic_error)?
+            .into_iter()
+            .map(|g| g.into())
+            .collect();
+
+        Ok(Response::new(AccountGrantResponse { grants }))
+    }
+
+    async fn set_account_grant(
+        &self,
+        request: Request<api::AccountGrant>,
+    ) -> Result<Response<Empty>, Status> {
+        let access = self.access(&request).await?;
+        let grant = request.into_inner();
+        access.require(&grant.account_id, AccountRole::Owner)?;
+        if grant.user_name == access.user_name {
+            return Err(Error::invalid_argument(
+                "user_name",
+                "owners cannot change their own role",
+            )
+            .into());
+        }
+        let household_id = self
+            .db
+            .get_account(&grant.account_id)
+            .await
+            .map_err(to_tonic_error)?
+            .and_then(|account| account.household_id)
+            .ok_or_else(|| Status::failed_precondition("account is not part of a household"))?;
+        let household = self
+            .db
+            .get_household(household_id)
+            .await
+            .map_err(to_tonic_error)?
+            .ok_or_else(|| Status::failed_precondition("household of the account is gone"))?;
+        if !household.members.contains(&grant.user_name) {
+            return Err(Status::failed_precondition(format!(
+                "{} is not a member of household {}",
+                grant.user_name, household.name
+            )));
+        }
+        let role = grant.role();
+        self.db
+            .save_account_grant(AccountGrant::new(grant.account_id, grant.user_name, role))
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn delete_account_grant(
+        &self,
+        request: Request<DeleteAccountGrantRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        access.require(&request.account_id, AccountRole::Owner)?;
+        if request.user_name == access.user_name {
+            return Err(Error::invalid_argument(
+                "user_name",
+                "owners cannot remove their own role",
+            )
+            .into());
+        }
+        self.db
+            .delete_account_grant(request.account_id, request.user_name)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn logout(&self, request: Request<Empty>) -> Result<Response<Empty>, Status> {
+        let claims = auth::claims(&request)?;
+        self.db
+            .revoke_token(claims.jti.clone(), claims.exp)
+            .await
+            .map_err(to_tonic_error)?;
+        self.sessions.revoke(&claims.jti);
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn create_user(
+        &self,
+        request: Request<CreateUserRequest>,
+    ) -> Result<Response<User>, Status> {
+        if !auth::claims(&request)?.admin {
+            return Err(Status::permission_denied("only admins can create users"));
+        }
+        let request = request.into_inner();
+        if request.user_name.trim().is_empty() {
+            return Err(Error::invalid_argument("user_name", "user name must not be empty").into());
+        }
+        let user = UserRecord {
+            id: Thing::from(("user", request.user_name.as_str())),
+            user_name: request.user_name,
+            password_hash: auth::hash_password(request.password).await?,
+            is_admin: request.is_admin,
+            sessions_since: 0,
+        };
+        self.db
+            .create_user(user.clone())
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(user.into()))
+    }
+
+    async fn change_password(
+        &self,
+        request: Request<ChangePasswordRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let claims = auth::claims(&request)?;
+        let request = request.into_inner();
+        let mut user = self
+            .db
+            .get_user(&claims.sub)
+            .await
+            .map_err(to_tonic_error)?
+            .ok_or_else(|| Status::not_found("user does not exist"))?;
+        if !auth::verify_password(request.old_password, user.password_hash.clone()).await {
+            return Err(Status::permission_denied("old password is wrong"));
+        }
+        user.password_hash = auth::hash_password(request.new_password).await?;
+        // sessions opened with the old password end, including this one
+        user.sessions_since = chrono::Utc::now().timestamp_millis();
+        let user_name = user.user_name.clone();
+        let since = user.sessions_since;
+        self.db.save_user(user).await.map_err(to_tonic_error)?;
+        self.sessions.revoke_user(&user_name, since);
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn detect_transfers(
+        &self,
+        request: Request<DetectTransfersRequest>,
+    ) -> Result<Response<TransferResponse>, Status> {
+        let access = self.access(&request).await?;
+        let window_days = match request.into_inner().window_days {
+            0 => DEFAULT_TRANSFER_WINDOW_DAYS,
+            days => days,
+        };
+        let transfers = self
+            .db
+            .detect_transfers(window_days as i64, &access)
+            .await
+            .map_err(to_tonic_error)?
+            .into_iter()
+            .map(|t| t.into())
+            .collect();
+
+        Ok(Response::new(TransferResponse { transfers }))
+    }
+
+    async fn get_transfers(
+        &self,
+        request: Request<Empty>,
+    ) -> Result<Response<TransferResponse>, Status> {
+        let access = self.access(&request).await?;
+        let transfers = self
+            .db
+            .get_transfers(&access)
+            .await
+            .map_err(to_tonic_error)?
+            .into_iter()
+            .map(|t| t.into())
+            .collect();
+
+        Ok(Response::new(TransferResponse { transfers }))
+    }
+
+    async fn set_transfer_status(
+        &self,
+        request: Request<SetTransferStatusRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        let id = Thing::from(("transfer", request.id.as_str()));
+        let transfer = self
+            .db
+            .get_transfer(id.clone())
+            .await
+            .map_err(to_tonic_error)?
+            .ok_or_else(|| Status::not_found("transfer not found"))?;
+        for transaction_id in [transfer.debit_id, transfer.credit_id] {
+            let transaction = self
+                .db
+                .get_transaction(transaction_id)
+                .await
+                .map_err(to_tonic_error)?
+                .ok_or_else(|| Status::not_found("transaction not found"))?;
+            access.require(&transaction.account_id, AccountRole::Editor)?;
+        }
+        self.db
+            .set_transfer_status(id, request.status())
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn delete_tag(
+        &self,
+        request: Request<DeleteTagRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        self.require_admin(&request, "change tags")?;
+        let request = request.into_inner();
+        let replacement = if request.replacement_id.is_empty() {
+            None
+        } else {
+            Some(Thing::from(("tag", request.replacement_id.as_str())))
+        };
+        self.db
+            .delete_tag(Thing::from(("tag", request.id.as_str())), replacement)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn merge_tags(
+        &self,
+        request: Request<MergeTagsRequest>,
+    ) -> Result<Response<Empty>, Status> {
+        self.require_admin(&request, "change tags")?;
+        let request = request.into_inner();
+        let sources = request
+            .source_ids
+            .iter()
+            .map(|id| Thing::from(("tag", id.as_str())))
+            .collect();
+        self.db
+            .merge_tags(sources, Thing::from(("tag", request.target_id.as_str())))
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn suggest_tags(
+        &self,
+        request: Request<SuggestTagsRequest>,
+    ) -> Result<Response<TagSuggestionResponse>, Status> {
+        let access = self.access(&request).await?;
+        let threshold = request.into_inner().auto_apply_threshold;
+        let suggestions = self
+            .db
+            .suggest_tags((threshold > 0.0).then_some(threshold), &access)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(TagSuggestionResponse { suggestions }))
+    }
+
+    #[instrument(name = "import", skip_all, fields(source = "upload", user = field::Empty))]
     async fn send_text_data(
         &self,
         request: Request<TextRequest>,
     ) -> Result<Response<TransactionResponse>, Status> {
-        let mt940_string = request.into_inner().data;
-        println!("Len: {}", mt940_string.len());
+        let claims = auth::claims(&request)?;
+        Span::current().record("user", claims.sub.as_str());
+        import_mt940(&self.db, request.into_inner().data, &claims.sub).await?;
 
-        let data = parse(mt940_string)
+        let access = self
+            .db
+            .get_access(&claims.sub)
             .await
-            .map_err(|e| Status::unknown(e.to_string()))?;
-        self.db.save_all(data).await.map_err(to_tonic_error)?;
-
+            .map_err(to_tonic_error)?;
         let data = self
             .db
-            .get_all_transactions()
+            .get_all_transactions(&access)
             .await
-            .map_err(|e| Status::new(tonic::Code::Aborted, e.to_string()))?;
-        let mut response = TransactionResponse::default();
-        response.transactions = data;
-        Ok(Response::new(response))
+            .map_err(to_tonic_error)?;
+        Ok(Response::new(TransactionResponse { transactions: data }))
     }
 
     async fn get_all_transactions(
         &self,
-        _request: Request<Empty>,
+        request: Request<Empty>,
     ) -> Result<Response<TransactionResponse>, Status> {
+        let access = self.access(&request).await?;
         let transactions = self
             .db
-            .get_all_transactions()
+            .get_all_transactions(&access)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(TransactionResponse { transactions }))
+    }
+
+    async fn list_transactions(
+        &self,
+        request: Request<ListTransactionsRequest>,
+    ) -> Result<Response<ListTransactionsResponse>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        let page_size = if request.page_size == 0 {
+            DEFAULT_PAGE_SIZE
+        } else {
+            request.page_size.min(MAX_PAGE_SIZE)
+        };
+        let cursor = (!request.cursor.is_empty()).then_some(request.cursor.clone());
+        let response = self
+            .db
+            .list_transactions(
+                TransactionFilter::from(request.filter.clone().unwrap_or_default()).scoped(&access),
+                request.sort(),
+                Some(page_size),
+                cursor,
+            )
             .await
-            .map_err(|e| Status::new(tonic::Code::Aborted, e.to_string()))?;
+            .map_err(to_tonic_error)?;
 
-        let mut response = TransactionResponse::default();
-        response.transactions = transactions;
         Ok(Response::new(response))
     }
 
+    async fn search_transactions(
+        &self,
+        request: Request<SearchTransactionsRequest>,
+    ) -> Result<Response<SearchTransactionsResponse>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        if request.query.trim().is_empty() {
+            return Err(Error::invalid_argument("query", "query must not be empty").into());
+        }
+        let limit = if request.limit == 0 {
+            DEFAULT_SEARCH_LIMIT
+        } else {
+            request.limit.min(MAX_PAGE_SIZE)
+        };
+        let matches = self
+            .db
+            .search_transactions(request.query, limit, &access)
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(SearchTransactionsResponse { matches }))
+    }
+
+    async fn export_transactions(
+        &self,
+        request: Request<ExportTransactionsRequest>,
+    ) -> Result<Response<Self::ExportTransactionsStream>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        let sort = request.sort();
+        let filter = TransactionFilter::from(request.filter.unwrap_or_default()).scoped(&access);
+        let db = self.db.clone();
+        // the bounded channel holds back the next chunk until the client caught up
+        let (send, recv) = tokio::sync::mpsc::channel(EXPORT_CHUNK_SIZE as usize);
+        self.jobs.spawn(async move {
+            let mut cursor = None;
+            loop {
+                // the total is not sent, counting each chunk would rescan all matches
+                let page = db
+                    .transaction_page(filter.clone(), sort, Some(EXPORT_CHUNK_SIZE), cursor)
+                    .await
+                    .map_err(to_tonic_error);
+                let page = match page {
+                    Ok(page) => page,
+                    Err(status) => {
+                        let _ = send.send(Err(status)).await;
+                        return;
+                    }
+                };
+                for transaction in page.transactions {
+                    if send.send(Ok(transaction)).await.is_err() {
+                        return;
+                    }
+                }
+                if page.next_cursor.is_empty() {
+                    return;
+                }
+                cursor = Some(page.next_cursor);
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(recv)))
+    }
+
     async fn get_all_transaction_partners(
         &self,
         _request: Request<Empty>,
@@ -98,21 +1022,23 @@ impl MoneyView for MoneyViewServer {
             .db
             .get_all_transaction_partners()
             .await
-            .map_err(|e| Status::new(tonic::Code::Aborted, e.to_string()))?;
+            .map_err(to_tonic_error)?;
 
-        let mut response = TransactionPartnerResponse::default();
-        response.transaction_partners = partners;
-        Ok(Response::new(response))
+        Ok(Response::new(TransactionPartnerResponse {
+            transaction_partners: partners,
+        }))
     }
 
     async fn get_partner_balance(
         &self,
-        _request: Request<Empty>,
+        request: Request<BalanceRequest>,
     ) -> Result<Response<BalanceResponse>, Status> {
+        let access = self.access(&request).await?;
+        let filter = TransactionFilter::from(request.into_inner()).scoped(&access);
         let mut balance_response = BalanceResponse::default();
         balance_response.expenses = self
             .db
-            .get_partner_balance(false)
+            .get_partner_balance(false, filter.clone())
             .await
             .map_err(to_tonic_error)?;
         balance_response.total_expenses = balance_response
@@ -122,7 +1048,7 @@ impl MoneyView for MoneyViewServer {
             .sum();
         balance_response.income = self
             .db
-            .get_partner_balance(true)
+            .get_partner_balance(true, filter)
             .await
             .map_err(to_tonic_error)?;
         balance_response.total_income = balance_response
@@ -135,12 +1061,14 @@ impl MoneyView for MoneyViewServer {
 
     async fn get_tag_balance(
         &self,
-        _request: Request<Empty>,
+        request: Request<BalanceRequest>,
     ) -> Result<Response<BalanceResponse>, Status> {
+        let access = self.access(&request).await?;
+        let filter = TransactionFilter::from(request.into_inner()).scoped(&access);
         let mut balance_response = BalanceResponse::default();
         balance_response.expenses = self
             .db
-            .get_tag_balance(false)
+            .get_tag_balance(false, filter.clone())
             .await
             .map_err(to_tonic_error)?;
         balance_response.total_expenses = balance_response
@@ -150,7 +1078,7 @@ impl MoneyView for MoneyViewServer {
             .sum();
         balance_response.income = self
             .db
-            .get_tag_balance(true)
+            .get_tag_balance(true, filter)
             .await
             .map_err(to_tonic_error)?;
         balance_response.total_income = balance_response
@@ -160,38 +1088,185 @@ impl MoneyView for MoneyViewServer {
             .sum();
         Ok(Response::new(balance_response))
     }
+
+    async fn get_cash_flow_series(
+        &self,
+        request: Request<CashFlowRequest>,
+    ) -> Result<Response<CashFlowResponse>, Status> {
+        let access = self.access(&request).await?;
+        let request = request.into_inner();
+        let points = self
+            .db
+            .get_cash_flow_series(
+                request.period(),
+                request.breakdown(),
+                TransactionFilter::from(request.filter.unwrap_or_default()).scoped(&access),
+            )
+            .await
+            .map_err(to_tonic_error)?;
+
+        Ok(Response::new(CashFlowResponse { points }))
+    }
 }
 
-fn to_tonic_error<T>(err: T) -> Status
+/// Parses a transaction id as returned in `Transaction.id`, plain keys are accepted as well.
+fn transaction_thing(id: &str) -> Thing {
+    surrealdb::sql::thing(id)
+        .ok()
+        .filter(|thing| thing.tb == "transaction")
+        .unwrap_or_else(|| Thing::from(("transaction", id)))
+}
+
+fn to_tonic_error<E>(err: E) -> Status
 where
-    T: ToString,
+    E: Into<Error>,
 {
-    Status::new(tonic::Code::Aborted, err.to_string())
+    Status::from(err.into())
+}
+
+/// Imports all MT940 files of a folder for `owner` and moves them into its `imported`
+/// subfolder, so they are read only once.
+async fn import_folder(db: &Database, folder: &Path, owner: &str) -> error::Result<()> {
+    let done = folder.join(IMPORTED_FOLDER);
+    let mut entries = tokio::fs::read_dir(folder).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if tokio::fs::metadata(&path).await?.is_file() {
+            import_file(db, &path, &done, owner).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Bank exports are often named after the IBAN, so the file name is logged as sensitive.
+#[instrument(name = "import", skip(db, path, done), fields(source = "folder", file = %sensitive(path.display())))]
+async fn import_file(db: &Database, path: &Path, done: &Path, owner: &str) -> error::Result<()> {
+    // bank exports are often latin-1, like in the upload the text is taken as is
+    let content = String::from_utf8_lossy(&tokio::fs::read(path).await?).into_owned();
+    import_mt940(db, content, owner).await?;
+
+    tokio::fs::create_dir_all(done).await?;
+    if let Some(file_name) = path.file_name() {
+        tokio::fs::rename(path, done.join(file_name)).await?;
+    }
+    Ok(())
+}
+
+/// Parses MT940 text and saves it for `user_name`. Accounts seen for the first time belong
+/// to the user, all others need at least the editor role.
+async fn import_mt940(db: &Database, content: String, user_name: &str) -> Result<(), Status> {
+    let result: Result<(), Status> = async {
+        let (transactions, statements) = parse(content).await.map_err(to_tonic_error)?;
+        let account_ids: Vec<String> = transactions
+            .iter()
+            .map(|t| t.account_id.clone())
+            .chain(statements.iter().map(|s| s.account_id.clone()))
+            .unique()
+            .collect();
+        db.claim_accounts(account_ids.clone(), user_name)
+            .await
+            .map_err(to_tonic_error)?;
+        let access = db.get_access(user_name).await.map_err(to_tonic_error)?;
+        for account_id in &account_ids {
+            access.require(account_id, AccountRole::Editor)?;
+        }
+
+        db.save_all(transactions).await.map_err(to_tonic_error)?;
+        db.save_statements(statements)
+            .await
+            .map_err(to_tonic_error)?;
+        info!(accounts = account_ids.len(), "import finished");
+        Ok(())
+    }
+    .await;
+    metrics::record_import("mt940", result.is_ok());
+    result
+}
+
+/// One span per HTTP request, for gRPC the path names the RPC.
+fn request_span(request: &axum::http::Request<axum::body::Body>) -> Span {
+    info_span!("request", method = %request.method(), path = %request.uri().path())
+}
+
+/// Creates the configured admin account on first start.
+async fn create_admin(db: &Database, user_name: &str, password: String) -> error::Result<()> {
+    if db.get_user(user_name).await?.is_some() {
+        return Ok(());
+    }
+    let user = UserRecord {
+        id: Thing::from(("user", user_name)),
+        user_name: user_name.to_string(),
+        password_hash: auth::hash_password(password).await?,
+        is_admin: true,
+        sessions_since: 0,
+    };
+    db.create_user(user).await
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
-    let web_addr = env::var("MONEY_VIEW_WEB_HOST").unwrap();
-    let web_dir = PathBuf::from("./web");
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            // logging is configured by the same file, so this goes to stderr directly
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    logging::init(&config.log)?;
+    let web_addr = config.server.bind.clone();
 
     // Service für statische Dateien
-    let static_service = get_service(ServeDir::new(web_dir.clone()))
+    let static_service = get_service(ServeDir::new(config.server.web_dir.clone()))
         .handle_error(|_| async { StatusCode::INTERNAL_SERVER_ERROR });
 
-    let host = env::var("MONEY_VIEW_DB_HOST").unwrap();
-    let database = env::var("MONEY_VIEW_DB_NAME").unwrap();
-    let user_name = env::var("MONEY_VIEW_USER").unwrap();
-    let password = env::var("MONEY_VIEW_DB_PASSWD").unwrap();
-    let namespace = env::var("MONEY_VIEW_DB_NAMESPACE").unwrap();
-
-    let db = Database::new(host, user_name, password, namespace, database)
-        .await
-        .map_err(|e| Status::new(tonic::Code::Aborted, e.to_string()))?;
+    let db = Database::new(&config.database).await?;
     db.init_db().await?;
+    db.keep_connected();
 
-    let money_view = MoneyViewServer { db };
-    let money_view = api::money_view_server::MoneyViewServer::new(money_view);
+    let sessions = Sessions::new(
+        config.auth.jwt_secret.as_bytes(),
+        chrono::Duration::hours(config.auth.token_hours),
+        db.get_revoked_tokens().await?,
+        db.get_sessions_since().await?,
+    );
+    if let (Some(user_name), Some(password)) =
+        (&config.auth.admin_user, &config.auth.admin_password)
+    {
+        create_admin(&db, user_name, password.clone()).await?;
+        // data imported before users existed belongs to the admin
+        db.claim_accounts(db.get_account_ids().await?, user_name)
+            .await?;
+        db.claim_budgets(user_name).await?;
+    }
+    if let Some(owner) = config.import_owner() {
+        for folder in &config.import.folders {
+            // a broken file must not keep the server from starting
+            if let Err(e) = import_folder(&db, folder, owner).await {
+                error!(folder = %folder.display(), error = %e, "import stopped");
+            }
+        }
+    }
+
+    let auth = Arc::new(AuthServer {
+        db: db.clone(),
+        sessions: sessions.clone(),
+    });
+    let metrics_db = db.clone();
+    let jobs = TaskTracker::new();
+    let money_view = Arc::new(MoneyViewServer {
+        db,
+        jobs: jobs.clone(),
+        sessions: sessions.clone(),
+    });
+    // REST und gRPC teilen sich dieselben Handler
+    let rest = Rest::new(money_view.clone(), auth.clone(), sessions.clone()).router()?;
+    let auth = tonic_web::enable(api::auth_server::AuthServer::from_arc(auth));
+    let money_view = InterceptedService::new(
+        api::money_view_server::MoneyViewServer::from_arc(money_view),
+        sessions,
+    );
     let money_view = tonic_web::enable(money_view);
 
     let reflection_1 = tonic_reflection::server::Builder::configure()
@@ -200,29 +1275,103 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let reflection_1a = tonic_reflection::server::Builder::configure()
         .register_encoded_file_descriptor_set(api::FILE_DESCRIPTOR_SET)
         .build_v1alpha()?;
-    let cors = CorsLayer::new()
-        .allow_headers(Any)
-        .allow_methods(Any)
-        .allow_origin(Any);
+    let cors = config.server.cors.layer()?;
+
+    let (health_reporter, health_service) = tonic_health::server::health_reporter();
+    let health = Health::new(metrics_db.clone(), health_reporter);
+    health.watch();
 
     let tonic_server = Routes::new(reflection_1)
         .add_service(reflection_1a)
+        .add_service(health_service)
+        .add_service(auth)
         .add_service(money_view);
-    let app = tonic_server.into_axum_router()// gRPC-Anfragen über Axum-Router
-    .layer(cors) // CORS-Layer für Axum
-    .fallback_service(static_service);
-    // Tokio select um beide Server gleichzeitig laufen zu lassen
-    tokio::select! {
-        _ = async {
-            // Starte den Axum HTTP Server
-            println!("Axum listening on {}", &web_addr);
-            let listener = tokio::net::TcpListener::bind(web_addr).await.unwrap();
-            axum::serve(listener, app).await.unwrap();
-        } => {}
+    let live = health.clone();
+    let ready = health.clone();
+    let app = tonic_server
+        .into_axum_router() // gRPC-Anfragen über Axum-Router
+        .route("/metrics", get(move || metrics::render(metrics_db.clone())))
+        .route("/healthz", get(move || live.clone().live()))
+        .route("/readyz", get(move || ready.clone().ready()))
+        .merge(rest)
+        .layer(cors) // CORS-Layer für Axum
+        .layer(axum::middleware::from_fn(metrics::track_requests))
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .fallback_service(static_service);
+    // Bei SIGTERM/SIGINT keine neuen Anfragen mehr annehmen und laufende abschließen
+    let shutdown = CancellationToken::new();
+    let drain_timeout = Duration::from_secs(config.server.shutdown_timeout);
+    // one deadline for the requests and the background jobs, set when the signal arrives
+    let deadline = Arc::new(OnceLock::new());
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        let health = health.clone();
+        let deadline = deadline.clone();
+        async move {
+            server::shutdown_signal().await;
+            deadline.get_or_init(|| tokio::time::Instant::now() + drain_timeout);
+            info!(timeout = ?drain_timeout, "shutting down, draining requests");
+            health.set_ready(false).await;
+            shutdown.cancel();
+        }
+    });
+
+    // Start ist abgeschlossen, ab jetzt nimmt der Server Anfragen an
+    health.set_ready(true).await;
+    match config.server.tls {
+        Some(tls) => {
+            // TLS wird im Prozess terminiert, SIGHUP lädt das Zertifikat neu
+            let config = tls.load().await?;
+            tls.reload_on_hangup(config.clone())?;
+            let handle = axum_server::Handle::new();
+            tokio::spawn({
+                let handle = handle.clone();
+                let shutdown = shutdown.clone();
+                async move {
+                    shutdown.cancelled().await;
+                    handle.graceful_shutdown(Some(drain_timeout));
+                }
+            });
+            info!(address = %web_addr, tls = true, "listening");
+            axum_server::bind_rustls(web_addr.parse()?, config)
+                .handle(handle)
+                .serve(app.into_make_service())
+                .await?;
+        }
+        None => {
+            info!(address = %web_addr, tls = false, "listening");
+            let listener = tokio::net::TcpListener::bind(web_addr).await?;
+            let serving = axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown.clone().cancelled_owned());
+            tokio::select! {
+                result = serving => result?,
+                _ = async {
+                    shutdown.cancelled().await;
+                    if let Some(deadline) = deadline.get() {
+                        tokio::time::sleep_until(*deadline).await;
+                    }
+                } => warn!("drain timed out, closing remaining connections"),
+            }
+        }
     }
 
+    // Hintergrundaufgaben wie laufende Exporte abschließen lassen
+    jobs.close();
+    let deadline = deadline
+        .get()
+        .copied()
+        .unwrap_or_else(|| tokio::time::Instant::now() + drain_timeout);
+    if tokio::time::timeout_at(deadline, jobs.wait())
+        .await
+        .is_err()
+    {
+        warn!(
+            running = jobs.len(),
+            "background jobs did not finish in time"
+        );
+    }
+    info!("stopped");
     Ok(())
 }
 
 mod parser;
-pub(crate) type ShortResult<T> = Result<T, Box<dyn std::error::Error>>;
@@ -0,0 +1,196 @@
+use std::time::{Duration, Instant};
+
+use axum::body::Body;
+use axum::extract::MatchedPath;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use tracing::span::{Attributes, Id};
+use tracing::{warn, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::database::Database;
+
+/// Target of the spans around every database method, see [`QueryLayer`].
+pub(crate) const DATABASE_TARGET: &str = "money_view::database";
+
+lazy_static! {
+    static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "money_view_requests_total",
+        "Handled requests by RPC and gRPC status code",
+        &["rpc", "code"]
+    )
+    .unwrap();
+    static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "money_view_request_duration_seconds",
+        "Time until the response headers were sent, by RPC",
+        &["rpc"]
+    )
+    .unwrap();
+    static ref IMPORTS: IntCounterVec = register_int_counter_vec!(
+        "money_view_imports_total",
+        "Imports by file format and result",
+        &["format", "result"]
+    )
+    .unwrap();
+    static ref PARSE_DURATION: HistogramVec = register_histogram_vec!(
+        "money_view_parse_duration_seconds",
+        "Time to parse an imported file, by file format",
+        &["format"]
+    )
+    .unwrap();
+    static ref QUERY_DURATION: HistogramVec = register_histogram_vec!(
+        "money_view_db_query_duration_seconds",
+        "Latency of database methods",
+        &["query"]
+    )
+    .unwrap();
+    static ref TRANSACTIONS: IntGauge =
+        register_int_gauge!("money_view_transactions", "Stored transactions").unwrap();
+    static ref TAGS: IntGauge = register_int_gauge!("money_view_tags", "Defined tags").unwrap();
+    static ref UNCATEGORISED: IntGauge = register_int_gauge!(
+        "money_view_uncategorised_transactions",
+        "Transactions with a line item on the default tag"
+    )
+    .unwrap();
+    static ref BUILD_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "money_view_build_info",
+        "Always 1, labeled with the version of the running server",
+        &["version"]
+    )
+    .unwrap();
+}
+
+/// Answers `GET /metrics` in the Prometheus text format. The stored item counts are read
+/// from the database on every scrape.
+pub(crate) async fn render(db: Database) -> Response {
+    BUILD_INFO
+        .with_label_values(&[env!("CARGO_PKG_VERSION")])
+        .set(1);
+    match db.get_statistics().await {
+        Ok(statistics) => {
+            TRANSACTIONS.set(statistics.transactions);
+            TAGS.set(statistics.tags);
+            UNCATEGORISED.set(statistics.uncategorised);
+        }
+        // the other metrics are still worth scraping
+        Err(e) => warn!(error = %e, "cannot count stored items for metrics"),
+    }
+    match TextEncoder::new().encode_to_string(&prometheus::gather()) {
+        Ok(text) => ([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], text).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
+
+/// Middleware counting requests and their latency per RPC.
+pub(crate) async fn track_requests(request: Request<Body>, next: Next) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str);
+    let rpc = rpc_name(request.method(), request.uri().path(), route);
+    let started = Instant::now();
+    let response = next.run(request).await;
+    REQUEST_DURATION
+        .with_label_values(&[&rpc])
+        .observe(started.elapsed().as_secs_f64());
+    // failed calls answer trailers-only with the status in the headers, successful ones
+    // send it in the trailers after the body
+    let code = match response.headers().get("grpc-status") {
+        Some(code) => code.to_str().unwrap_or("unknown").to_string(),
+        None if response.status().is_success() => "0".to_string(),
+        None => format!("http_{}", response.status().as_u16()),
+    };
+    REQUESTS.with_label_values(&[&rpc, &code]).inc();
+    response
+}
+
+/// `/package.Service/Method` for gRPC calls and HTTP method plus route for the JSON routes
+/// under `/api`. Everything else is counted as `http` to keep the number of label values
+/// small.
+fn rpc_name(http_method: &Method, path: &str, route: Option<&str>) -> String {
+    match path.trim_start_matches('/').split_once('/') {
+        Some((service, method))
+            if service.contains('.') && !method.is_empty() && !method.contains('/') =>
+        {
+            path.to_string()
+        }
+        _ => match route {
+            Some(route) if route.starts_with("/api/") => format!("{} {}", http_method, route),
+            _ => "http".to_string(),
+        },
+    }
+}
+
+pub(crate) fn record_import(format: &str, success: bool) {
+    let result = if success { "success" } else { "failure" };
+    IMPORTS.with_label_values(&[format, result]).inc();
+}
+
+pub(crate) fn observe_parse(format: &str, duration: Duration) {
+    PARSE_DURATION
+        .with_label_values(&[format])
+        .observe(duration.as_secs_f64());
+}
+
+/// Tracing layer that turns the spans of the database methods into latency observations,
+/// labeled with the method name.
+pub(crate) struct QueryLayer;
+
+struct QueryStart(Instant);
+
+impl<S> Layer<S> for QueryLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(QueryStart(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(&id) {
+            if let Some(QueryStart(started)) = span.extensions().get::<QueryStart>() {
+                QUERY_DURATION
+                    .with_label_values(&[span.name()])
+                    .observe(started.elapsed().as_secs_f64());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rpc_name() {
+        assert_eq!(
+            rpc_name(
+                &Method::POST,
+                "/money_view.MoneyView/GetAllTransactions",
+                Some("/money_view.MoneyView/*rest")
+            ),
+            "/money_view.MoneyView/GetAllTransactions"
+        );
+        assert_eq!(
+            rpc_name(&Method::GET, "/api/budgets", Some("/api/budgets")),
+            "GET /api/budgets"
+        );
+        assert_eq!(
+            rpc_name(&Method::POST, "/api/budgets", Some("/api/budgets")),
+            "POST /api/budgets"
+        );
+        assert_eq!(rpc_name(&Method::GET, "/assets/main.dart.js", None), "http");
+        assert_eq!(rpc_name(&Method::GET, "/index.html", None), "http");
+        assert_eq!(rpc_name(&Method::GET, "/metrics", Some("/metrics")), "http");
+    }
+}
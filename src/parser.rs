@@ -1,12 +1,21 @@
 use std::collections::HashMap;
+use std::time::Instant;
 
-use crate::{database::TransactionRecord, ShortResult};
+use crate::{
+    database::{StatementRecord, TransactionRecord},
+    error::{Error, Result},
+    metrics,
+};
 use itertools::Itertools;
 use lazy_static::lazy_static;
-use mt940::{parse_mt940, sanitizers::sanitize, DebitOrCredit, ExtDebitOrCredit, Message};
+use mt940::{parse_mt940, sanitizers::sanitize, Balance, DebitOrCredit, ExtDebitOrCredit, Message};
 use rayon::prelude::*;
 use regex::Regex;
 use rust_decimal::Decimal;
+use tracing::{debug, info, instrument};
+
+/// Request field holding the MT940 text, named in the violations of invalid input.
+const INPUT_FIELD: &str = "data";
 
 lazy_static! {
     static ref FIELD_KEY_PARTIAL_ERAZER: Regex =
@@ -14,10 +23,14 @@ lazy_static! {
     static ref FIELD_KEY_COMPLETE_ERAZER: Regex = Regex::new(r"\?\d{2}").unwrap();
     static ref USAGE_KEY_RENAMER: Regex =
         Regex::new(r"([A-Z])(REF|RED|REN|VWZ|BAN|IC)(\+|: )").unwrap();
+    static ref ORIGINAL_AMOUNT: Regex =
+        Regex::new(r"/OCMT/\s*([A-Z]{3})\s*(\d+(?:,\d*)?)").unwrap();
 }
 
-fn parse_amount(amount: Decimal, debit: &ExtDebitOrCredit) -> ShortResult<f32> {
-    let val: f32 = amount.try_into()?;
+fn parse_amount(amount: Decimal, debit: &ExtDebitOrCredit) -> Result<f32> {
+    let val: f32 = amount
+        .try_into()
+        .map_err(|e| Error::invalid_argument(INPUT_FIELD, e))?;
     let val = val
         * match debit {
             mt940::ExtDebitOrCredit::Debit => -1.0,
@@ -61,15 +74,27 @@ fn remove_numbers(input: String) -> String {
         .into_owned()
 }
 
-pub async fn parse(input: String) -> ShortResult<Vec<TransactionRecord>> {
+#[instrument(skip_all, fields(bytes = input.len()))]
+pub async fn parse(input: String) -> Result<(Vec<TransactionRecord>, Vec<StatementRecord>)> {
+    let started = Instant::now();
     let input = pre_parser(input).await?;
-    println!("preparse: {:?}", input.len());
-    let res = parse_mt940(&input)?;
-    println!("messages: {:?}", res.len());
-    Ok(parse_messages(res).await?)
+    debug!(bytes = input.len(), "preparsed");
+    let res = parse_mt940(&input).map_err(|e| Error::invalid_argument(INPUT_FIELD, e))?;
+    debug!(messages = res.len(), "parsed messages");
+    let statements: Vec<StatementRecord> = res.iter().map(statement_of).collect();
+    let transactions = parse_messages(res).await?;
+    let elapsed = started.elapsed();
+    metrics::observe_parse("mt940", elapsed);
+    info!(
+        transactions = transactions.len(),
+        statements = statements.len(),
+        elapsed_ms = elapsed.as_millis() as u64,
+        "parsed MT940"
+    );
+    Ok((transactions, statements))
 }
 
-pub async fn pre_parser(input: String) -> ShortResult<String> {
+pub async fn pre_parser(input: String) -> Result<String> {
     let input = input
         .replace("\r\n", "\n")
         .replace("\n?", "?")
@@ -98,7 +123,7 @@ pub async fn pre_parser(input: String) -> ShortResult<String> {
     Ok(recv.await?)
 }
 
-async fn parse_messages(input: Vec<Message>) -> ShortResult<Vec<TransactionRecord>> {
+async fn parse_messages(input: Vec<Message>) -> Result<Vec<TransactionRecord>> {
     let (send, recv) = tokio::sync::oneshot::channel();
     rayon::spawn(move || {
         let result: Vec<TransactionRecord> = input
@@ -113,26 +138,36 @@ async fn parse_messages(input: Vec<Message>) -> ShortResult<Vec<TransactionRecor
     Ok(result)
 }
 fn process_single_message(input: &Message) -> Vec<TransactionRecord> {
-    let mut balance: f32 = input.opening_balance.amount.try_into().unwrap_or_default();
-    if input.opening_balance.debit_credit_indicator == DebitOrCredit::Debit {
-        balance *= -1.0;
-    }
+    let mut balance = balance_amount(&input.opening_balance);
     let account_id = input.account_id.clone();
+    let currency = input.opening_balance.iso_currency_code.clone();
 
     let result: Vec<TransactionRecord> = input
         .statement_lines
         .iter()
-        .map(|line| {
-            let mut transaction = TransactionRecord::default();
-            transaction.total_amount =
-                parse_amount(line.amount.clone(), &line.ext_debit_credit_indicator)
-                    .unwrap_or_default();
+        .enumerate()
+        .map(|(position, line)| {
+            let mut transaction = TransactionRecord {
+                position: position as u32,
+                total_amount: parse_amount(line.amount, &line.ext_debit_credit_indicator)
+                    .unwrap_or_default(),
+                ..Default::default()
+            };
             if let Some(date) = line.entry_date {
                 transaction.date = date;
             }
             balance += transaction.total_amount;
             transaction.balance_after_transaction = balance;
             transaction.account_id = account_id.clone();
+            transaction.currency = currency.clone();
+            if let Some((currency, amount)) = line
+                .supplementary_details
+                .as_deref()
+                .and_then(original_amount)
+            {
+                transaction.original_currency = currency;
+                transaction.original_amount = amount.copysign(transaction.total_amount);
+            }
             if let Some(info) = line.information_to_account_owner.clone() {
                 let info: Vec<&str> = info.split("?").collect();
 
@@ -148,6 +183,14 @@ fn process_single_message(input: &Message) -> Vec<TransactionRecord> {
                 ));
                 transaction.description = info.get(4).unwrap_or(&"").to_string();
                 transaction.partner_name = info.get(3).unwrap_or(&"").to_string();
+                transaction.partner_id = info.get(2).unwrap_or(&"").to_string();
+                // some banks put the original amount into the purpose instead of :61:
+                if transaction.original_currency.is_empty() {
+                    if let Some((currency, amount)) = original_amount(&transaction.description) {
+                        transaction.original_currency = currency;
+                        transaction.original_amount = amount.copysign(transaction.total_amount);
+                    }
+                }
             }
             transaction
         })
@@ -155,7 +198,38 @@ fn process_single_message(input: &Message) -> Vec<TransactionRecord> {
     result
 }
 
-fn parse_86_line(line: String) -> ShortResult<String> {
+fn balance_amount(balance: &Balance) -> f32 {
+    let amount: f32 = balance.amount.try_into().unwrap_or_default();
+    if balance.debit_credit_indicator == DebitOrCredit::Debit {
+        -amount
+    } else {
+        amount
+    }
+}
+
+fn statement_of(input: &Message) -> StatementRecord {
+    StatementRecord {
+        id: surrealdb::sql::Thing::from((
+            "statement",
+            format!("{}-{}", input.account_id, input.closing_balance.date).as_str(),
+        )),
+        account_id: input.account_id.clone(),
+        opening_date: input.opening_balance.date,
+        opening_balance: balance_amount(&input.opening_balance),
+        closing_date: input.closing_balance.date,
+        closing_balance: balance_amount(&input.closing_balance),
+        currency: input.closing_balance.iso_currency_code.clone(),
+    }
+}
+
+/// Original currency and amount of a payment abroad, given as `/OCMT/USD12,34/`.
+fn original_amount(input: &str) -> Option<(String, f32)> {
+    let captures = ORIGINAL_AMOUNT.captures(input)?;
+    let amount = captures[2].replace(',', ".").parse().ok()?;
+    Some((captures[1].to_string(), amount))
+}
+
+fn parse_86_line(line: String) -> Result<String> {
     let mut transaction_id = "".to_string();
     let mut partner_id = "".to_string();
     let mut partner_name = "".to_string();
@@ -163,31 +237,31 @@ fn parse_86_line(line: String) -> ShortResult<String> {
     let line = line.replace(": ", "+");
     let parsed_line = parse_string(line.as_str());
 
-    if let Some(svwz) = parsed_line.get(&"SVWZ".to_string()) {
+    if let Some(svwz) = parsed_line.get("SVWZ") {
         transaction_description = svwz.clone();
     }
 
-    if let Some(kref) = parsed_line.get(&"KREF".to_string()) {
+    if let Some(kref) = parsed_line.get("KREF") {
         transaction_id = kref.clone();
     } else if let Some(eref) = parsed_line.get("EREF") {
         transaction_id = eref.clone();
     }
 
     if let Some(cren) = parsed_line.get("CREN") {
-        partner_name = format!("{}", cren);
+        partner_name = cren.clone();
     } else if line.contains("00Abschluss") {
         partner_name = "VR-BANK UCKERMARK-RANDOW".to_string();
     }
 
     if let Some(mref) = parsed_line.get("MREF") {
-        partner_id = format!("{}", mref);
+        partner_id = mref.clone();
     } else if let Some(iban) = parsed_line.get("IBAN") {
-        partner_id = format!("{}", iban);
-    } else if partner_name != "".to_string() {
+        partner_id = iban.clone();
+    } else if !partner_name.is_empty() {
         partner_id = partner_name.clone();
     }
 
-    if partner_id == "OFFLINE".to_string() {
+    if partner_id == "OFFLINE" {
         partner_id = partner_name.clone();
     }
 
@@ -296,4 +370,15 @@ mod tests {
         let result = parse(input).await.unwrap();
         dbg!(result);
     }
+
+    #[test]
+    fn test_original_amount_stays_in_purpose() {
+        let line = ":86:106?00Kartenzahlung?20EREF+4711?21SVWZ+Hotel Boston /OCMT/USD12,34/?32HOTEL BOSTON";
+        let line = remove_numbers(move_inserted_to_end(short_process(line.to_string())));
+        let parsed = parse_86_line(line).unwrap();
+        let info: Vec<&str> = parsed.split("?").collect();
+        assert_eq!(info.len(), 5);
+        assert_eq!(info[4], "Hotel Boston /OCMT/USD12,34/");
+        assert_eq!(original_amount(info[4]), Some(("USD".to_string(), 12.34)));
+    }
 }
@@ -0,0 +1,156 @@
+use chrono::{Duration, Months, NaiveDate};
+use itertools::Itertools;
+use rayon::prelude::*;
+use surrealdb::sql::Thing;
+
+use crate::database::{RecurringPayment, TransactionRecord};
+
+const MIN_OCCURRENCES: usize = 3;
+/// Relative deviation from the typical amount that still belongs to a series.
+const AMOUNT_TOLERANCE: f32 = 0.2;
+/// Weekly, biweekly, monthly, quarterly and yearly payments.
+const KNOWN_INTERVALS: [i64; 5] = [7, 14, 30, 91, 365];
+
+/// Finds series of payments to the same partner with a regular interval and a similar amount.
+/// Partners are identified by mandate reference or IBAN and fall back to the partner name.
+pub(crate) fn detect(
+    transactions: Vec<TransactionRecord>,
+    today: NaiveDate,
+) -> Vec<RecurringPayment> {
+    transactions
+        .into_iter()
+        .into_group_map_by(|t| {
+            (
+                t.account_id.clone(),
+                t.partner_key().to_string(),
+                t.total_amount < 0.0,
+            )
+        })
+        .into_par_iter()
+        .filter_map(|((account_id, partner, _), series)| {
+            detect_series(account_id, partner, series, today)
+        })
+        .collect()
+}
+
+fn detect_series(
+    account_id: String,
+    partner: String,
+    mut series: Vec<TransactionRecord>,
+    today: NaiveDate,
+) -> Option<RecurringPayment> {
+    if series.len() < MIN_OCCURRENCES {
+        return None;
+    }
+    series.sort_by_key(|t| t.date);
+
+    let typical_amount = median(series.iter().map(|t| t.total_amount.abs()).collect());
+    let members: Vec<&TransactionRecord> = series
+        .iter()
+        .filter(|t| {
+            (t.total_amount.abs() - typical_amount).abs() <= typical_amount * AMOUNT_TOLERANCE
+        })
+        .collect();
+    if members.len() < MIN_OCCURRENCES {
+        return None;
+    }
+
+    let intervals: Vec<i64> = members
+        .windows(2)
+        .map(|pair| (pair[1].date - pair[0].date).num_days())
+        .collect();
+    let typical_interval = median(intervals.iter().map(|days| *days as f32).collect()) as i64;
+    let interval = *KNOWN_INTERVALS
+        .iter()
+        .find(|interval| (typical_interval - **interval).abs() <= tolerance(**interval))?;
+    let regular = intervals
+        .iter()
+        .filter(|days| (**days - interval).abs() <= tolerance(interval))
+        .count();
+    if regular * 4 < intervals.len() * 3 {
+        return None;
+    }
+
+    let first = members.first()?;
+    let last = members.last()?;
+    let previous = members.get(members.len() - 2)?;
+    let next_expected_date = next_date(last.date, interval);
+
+    Some(RecurringPayment {
+        id: Thing::from((
+            "recurring_payment",
+            format!("{}-{}-{}", account_id, partner, last.total_amount < 0.0).as_str(),
+        )),
+        partner_name: last.partner_name.clone(),
+        partner_id: last.partner_id.clone(),
+        account_id,
+        interval_days: interval as u32,
+        amount: last.total_amount,
+        previous_amount: previous.total_amount,
+        last_date: last.date,
+        next_expected_date,
+        transaction_ids: members.iter().map(|t| t.id.clone()).collect(),
+        price_changed: (last.total_amount - previous.total_amount).abs() >= 0.01,
+        missed: today > next_expected_date + Duration::days(tolerance(interval)),
+        is_new: (today - first.date).num_days() <= interval * MIN_OCCURRENCES as i64,
+    })
+}
+
+/// Allowed deviation from the nominal interval in days.
+fn tolerance(interval: i64) -> i64 {
+    (interval / 10).max(2)
+}
+
+/// Keeps the day of month for monthly and longer intervals.
+pub(crate) fn next_date(date: NaiveDate, interval: i64) -> NaiveDate {
+    match interval {
+        30 => date + Months::new(1),
+        91 => date + Months::new(3),
+        365 => date + Months::new(12),
+        days => date + Duration::days(days),
+    }
+}
+
+pub(crate) fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    values.get(values.len() / 2).copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(date: NaiveDate, amount: f32) -> TransactionRecord {
+        TransactionRecord {
+            id: Thing::from(("transaction", date.to_string().as_str())),
+            account_id: "15091704/3000185000".to_string(),
+            date,
+            total_amount: amount,
+            partner_name: "Ev. Kirchengemeinde Torgelow".to_string(),
+            partner_id: "0035-5250".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_detect_monthly() {
+        let date = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        let transactions = vec![
+            payment(date(3, 15), -100.0),
+            payment(date(4, 16), -100.0),
+            payment(date(5, 15), -100.0),
+            payment(date(6, 14), -104.5),
+            payment(date(6, 20), -2.0),
+        ];
+
+        let result = detect(transactions, date(7, 20));
+        assert_eq!(result.len(), 1);
+        let series = &result[0];
+        assert_eq!(series.interval_days, 30);
+        assert_eq!(series.transaction_ids.len(), 4);
+        assert_eq!(series.next_expected_date, date(7, 14));
+        assert!(series.price_changed);
+        assert!(series.missed);
+        assert!(!series.is_new);
+    }
+}
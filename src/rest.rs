@@ -0,0 +1,560 @@
+use std::sync::Arc;
+
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{header, Extensions, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use axum_extra::extract::{Query, QueryRejection};
+use axum_extra::json_lines::JsonLines;
+use serde::Serialize;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
+use tonic::{Code, Request, Status};
+use tonic_types::StatusExt;
+use utoipa::openapi::path::{OperationBuilder, ParameterBuilder, ParameterIn, ParameterStyle};
+use utoipa::openapi::request_body::RequestBodyBuilder;
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::openapi::{
+    ComponentsBuilder, Content, HttpMethod, InfoBuilder, OpenApi, OpenApiBuilder, PathItem,
+    PathsBuilder, Ref, RefOr, Required, ResponseBuilder, Schema, SecurityRequirement,
+};
+use utoipa::ToSchema;
+
+use crate::api::auth_server::Auth;
+use crate::api::money_view_server::MoneyView;
+use crate::api::{
+    AccountGrant, AccountGrantRequest, AccountGrantResponse, AccountHouseholdRequest,
+    AccountResponse, AnomalyRequest, AnomalyResponse, BalanceHistoryRequest,
+    BalanceHistoryResponse, BalanceRequest, BalanceResponse, Budget, BudgetResponse,
+    BudgetStatusRequest, BudgetStatusResponse, CashFlowRequest, CashFlowResponse,
+    ChangePasswordRequest, CreateUserRequest, DeleteAccountGrantRequest, DeleteBudgetRequest,
+    DeleteManualAccountRequest, DeleteTagRequest, DetectTransfersRequest, DismissAnomalyRequest,
+    Empty, ExchangeRateRequest, ExchangeRateResponse, ExportTransactionsRequest, ForecastRequest,
+    ForecastResponse, Household, HouseholdMemberRequest, HouseholdResponse,
+    ImportExchangeRatesRequest, ImportExchangeRatesResponse, ListTransactionsRequest,
+    ListTransactionsResponse, LoginRequest, LoginResponse, ManualAccount, ManualAccountResponse,
+    MergeTagsRequest, RecurringPaymentResponse, SearchTransactionsRequest,
+    SearchTransactionsResponse, SetTransferStatusRequest, SuggestTagsRequest, Tag, TagResponse,
+    TagSuggestionResponse, TagTestResponse, TextRequest, Transaction, TransactionPartnerResponse,
+    TransactionResponse, TransferResponse, User,
+};
+use crate::auth::Sessions;
+use crate::error::{Error, Result};
+use crate::{AuthServer, MoneyViewServer};
+
+const OPENAPI_PATH: &str = "/api/openapi.json";
+const JSON: &str = "application/json";
+const JSON_LINES: &str = "application/x-ndjson";
+const BEARER: &str = "bearer";
+/// Names of the whole query string and body in the violations of malformed requests.
+const QUERY_FIELD: &str = "query";
+const BODY_FIELD: &str = "body";
+/// Largest request body, the default message limit of tonic, so uploads like `/api/import`
+/// are accepted up to the same size as over gRPC.
+const MAX_BODY_SIZE: usize = 4 * 1024 * 1024;
+
+type Schemas = Vec<(String, RefOr<Schema>)>;
+
+/// JSON routes for clients that cannot speak gRPC-web, like scripts.
+///
+/// Every route calls the same handler as the RPC it mirrors, with the bearer token of the
+/// `Authorization` header checked like for gRPC. Reads are `GET` with the request message
+/// as query parameters (repeated fields repeat the key), all others `POST` a JSON body.
+#[derive(Debug, Clone)]
+pub(crate) struct Rest {
+    money_view: Arc<MoneyViewServer>,
+    auth: Arc<AuthServer>,
+    sessions: Sessions,
+}
+
+impl Rest {
+    pub(crate) fn new(
+        money_view: Arc<MoneyViewServer>,
+        auth: Arc<AuthServer>,
+        sessions: Sessions,
+    ) -> Self {
+        Self {
+            money_view,
+            auth,
+            sessions,
+        }
+    }
+
+    /// The `/api` routes and the OpenAPI document describing them.
+    pub(crate) fn router(self) -> Result<Router> {
+        let document = openapi()
+            .to_pretty_json()
+            .map_err(|e| Error::Internal(e.to_string()))?;
+        Ok(money_view_routes()
+            .route("/api/login", post(login))
+            .route("/api/transactions/export", post(export_transactions))
+            .route(
+                OPENAPI_PATH,
+                get(move || async move { ([(header::CONTENT_TYPE, JSON)], document) }),
+            )
+            .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
+            .with_state(self))
+    }
+
+    /// Request as the gRPC service receives it, with the claims of a valid bearer token.
+    fn authenticate<T>(&self, headers: HeaderMap, message: T) -> Result<Request<T>> {
+        let request =
+            Request::from_parts(MetadataMap::from_headers(headers), Extensions::new(), ());
+        let (metadata, extensions, ()) = self.sessions.clone().call(request)?.into_parts();
+        Ok(Request::from_parts(metadata, extensions, message))
+    }
+}
+
+/// Declares the routes mirroring `MoneyView` RPCs together with their documentation.
+macro_rules! rest_api {
+    ($($method:ident $path:literal => $rpc:ident($request:ty) -> $response:ty;)*) => {
+        fn money_view_routes() -> Router<Rest> {
+            Router::new()
+                $(.route($path, rest_api!(@$method $rpc $request)))*
+        }
+
+        fn money_view_paths(schemas: &mut Schemas) -> Vec<(&'static str, PathItem)> {
+            vec![$((
+                $path,
+                operation(
+                    rest_api!(@http $method),
+                    stringify!($rpc),
+                    optional_schema::<$request>(schemas),
+                    (JSON, schema::<$response>(schemas)),
+                    true,
+                ),
+            )),*]
+        }
+    };
+    (@get $rpc:ident $request:ty) => {
+        get(
+            |State(rest): State<Rest>,
+             headers: HeaderMap,
+             query: Result<Query<$request>, QueryRejection>| async move {
+                let Query(message) = query.map_err(|e| Error::invalid_argument(QUERY_FIELD, e))?;
+                let request = rest.authenticate(headers, message)?;
+                Ok::<_, ApiError>(Json(rest.money_view.$rpc(request).await?.into_inner()))
+            },
+        )
+    };
+    (@post $rpc:ident $request:ty) => {
+        post(
+            |State(rest): State<Rest>,
+             headers: HeaderMap,
+             body: Result<Json<$request>, JsonRejection>| async move {
+                let Json(message) = body.map_err(|e| Error::invalid_argument(BODY_FIELD, e))?;
+                let request = rest.authenticate(headers, message)?;
+                Ok::<_, ApiError>(Json(rest.money_view.$rpc(request).await?.into_inner()))
+            },
+        )
+    };
+    (@http get) => {
+        HttpMethod::Get
+    };
+    (@http post) => {
+        HttpMethod::Post
+    };
+}
+
+rest_api! {
+    get "/api/transactions" => get_all_transactions(Empty) -> TransactionResponse;
+    post "/api/transactions/list" => list_transactions(ListTransactionsRequest) -> ListTransactionsResponse;
+    get "/api/transactions/search" => search_transactions(SearchTransactionsRequest) -> SearchTransactionsResponse;
+    post "/api/import" => send_text_data(TextRequest) -> TransactionResponse;
+    get "/api/partners" => get_all_transaction_partners(Empty) -> TransactionPartnerResponse;
+    get "/api/balances/partners" => get_partner_balance(BalanceRequest) -> BalanceResponse;
+    get "/api/balances/tags" => get_tag_balance(BalanceRequest) -> BalanceResponse;
+    get "/api/balances/history" => get_balance_history(BalanceHistoryRequest) -> BalanceHistoryResponse;
+    post "/api/cash-flow" => get_cash_flow_series(CashFlowRequest) -> CashFlowResponse;
+    get "/api/budgets" => get_budgets(Empty) -> BudgetResponse;
+    post "/api/budgets" => set_budget(Budget) -> Empty;
+    post "/api/budgets/delete" => delete_budget(DeleteBudgetRequest) -> Empty;
+    get "/api/budgets/status" => get_budget_status(BudgetStatusRequest) -> BudgetStatusResponse;
+    get "/api/recurring-payments" => get_recurring_payments(Empty) -> RecurringPaymentResponse;
+    post "/api/recurring-payments/detect" => detect_recurring_payments(Empty) -> RecurringPaymentResponse;
+    get "/api/forecast" => get_forecast(ForecastRequest) -> ForecastResponse;
+    get "/api/anomalies" => get_anomalies(AnomalyRequest) -> AnomalyResponse;
+    post "/api/anomalies/dismiss" => dismiss_anomaly(DismissAnomalyRequest) -> Empty;
+    get "/api/manual-accounts" => get_manual_accounts(Empty) -> ManualAccountResponse;
+    post "/api/manual-accounts" => set_manual_account(ManualAccount) -> Empty;
+    post "/api/manual-accounts/delete" => delete_manual_account(DeleteManualAccountRequest) -> Empty;
+    get "/api/transfers" => get_transfers(Empty) -> TransferResponse;
+    post "/api/transfers/detect" => detect_transfers(DetectTransfersRequest) -> TransferResponse;
+    post "/api/transfers/status" => set_transfer_status(SetTransferStatusRequest) -> Empty;
+    get "/api/exchange-rates" => get_exchange_rates(ExchangeRateRequest) -> ExchangeRateResponse;
+    post "/api/exchange-rates/import" => import_exchange_rates(ImportExchangeRatesRequest) -> ImportExchangeRatesResponse;
+    post "/api/logout" => logout(Empty) -> Empty;
+    post "/api/users" => create_user(CreateUserRequest) -> User;
+    post "/api/users/password" => change_password(ChangePasswordRequest) -> Empty;
+    get "/api/households" => get_households(Empty) -> HouseholdResponse;
+    post "/api/households" => create_household(Household) -> Household;
+    post "/api/households/members" => add_household_member(HouseholdMemberRequest) -> Empty;
+    post "/api/households/members/remove" => remove_household_member(HouseholdMemberRequest) -> Empty;
+    get "/api/accounts" => get_accounts(Empty) -> AccountResponse;
+    post "/api/accounts/household" => set_account_household(AccountHouseholdRequest) -> Empty;
+    get "/api/accounts/grants" => get_account_grants(AccountGrantRequest) -> AccountGrantResponse;
+    post "/api/accounts/grants" => set_account_grant(AccountGrant) -> Empty;
+    post "/api/accounts/grants/delete" => delete_account_grant(DeleteAccountGrantRequest) -> Empty;
+    get "/api/tags" => get_tags(Empty) -> TagResponse;
+    post "/api/tags" => set_tag(Tag) -> Empty;
+    post "/api/tags/test" => test_tag(Tag) -> TagTestResponse;
+    post "/api/tags/delete" => delete_tag(DeleteTagRequest) -> Empty;
+    post "/api/tags/merge" => merge_tags(MergeTagsRequest) -> Empty;
+    post "/api/tags/suggest" => suggest_tags(SuggestTagsRequest) -> TagSuggestionResponse;
+}
+
+/// `POST /api/login`, the only route without bearer token.
+async fn login(
+    State(rest): State<Rest>,
+    body: Result<Json<LoginRequest>, JsonRejection>,
+) -> Result<Json<LoginResponse>, ApiError> {
+    let Json(message) = body.map_err(|e| Error::invalid_argument(BODY_FIELD, e))?;
+    Ok(Json(
+        rest.auth.login(Request::new(message)).await?.into_inner(),
+    ))
+}
+
+/// `POST /api/transactions/export`, streamed as one JSON transaction per line.
+async fn export_transactions(
+    State(rest): State<Rest>,
+    headers: HeaderMap,
+    body: Result<Json<ExportTransactionsRequest>, JsonRejection>,
+) -> Result<Response, ApiError> {
+    let Json(message) = body.map_err(|e| Error::invalid_argument(BODY_FIELD, e))?;
+    let request = rest.authenticate(headers, message)?;
+    let transactions = rest.money_view.export_transactions(request).await?;
+    Ok(JsonLines::new(transactions.into_inner()).into_response())
+}
+
+/// Failed call answered with the HTTP status matching the gRPC code and an [`ErrorBody`].
+#[derive(Debug)]
+struct ApiError(Status);
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        Self(status)
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(error: Error) -> Self {
+        Self(error.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.0;
+        let retry_after = status
+            .get_details_retry_info()
+            .and_then(|info| info.retry_delay)
+            .map(|delay| delay.as_secs());
+        let field_violations = status
+            .get_details_bad_request()
+            .map(|bad_request| bad_request.field_violations)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|violation| FieldViolation {
+                field: violation.field,
+                description: violation.description,
+            })
+            .collect();
+        let body = ErrorBody {
+            code: i32::from(status.code()),
+            message: status.message().to_string(),
+            field_violations,
+            retry_after,
+        };
+        let mut response = (http_status(status.code()), Json(body)).into_response();
+        if let Some(seconds) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+/// Error answer of every route.
+#[derive(Debug, Serialize, ToSchema)]
+struct ErrorBody {
+    /// gRPC status code, e.g. 3 for INVALID_ARGUMENT
+    code: i32,
+    message: String,
+    /// Request fields with invalid values
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    field_violations: Vec<FieldViolation>,
+    /// Seconds to wait before trying again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct FieldViolation {
+    field: String,
+    description: String,
+}
+
+/// Same mapping as the HTTP/JSON transcoding of gRPC services by Google APIs.
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// OpenAPI 3.1 document of all `/api` routes, generated from the protobuf messages.
+fn openapi() -> OpenApi {
+    let mut schemas = Schemas::new();
+    let mut paths = PathsBuilder::new();
+    for (path, item) in money_view_paths(&mut schemas) {
+        paths = paths.path(path, item);
+    }
+    paths = paths
+        .path(
+            "/api/login",
+            operation(
+                HttpMethod::Post,
+                "login",
+                optional_schema::<LoginRequest>(&mut schemas),
+                (JSON, schema::<LoginResponse>(&mut schemas)),
+                false,
+            ),
+        )
+        .path(
+            "/api/transactions/export",
+            operation(
+                HttpMethod::Post,
+                "export_transactions",
+                optional_schema::<ExportTransactionsRequest>(&mut schemas),
+                (JSON_LINES, schema::<Transaction>(&mut schemas)),
+                true,
+            ),
+        );
+    schema::<ErrorBody>(&mut schemas);
+
+    OpenApiBuilder::new()
+        .info(
+            InfoBuilder::new()
+                .title("Money View")
+                .version(env!("CARGO_PKG_VERSION"))
+                .description(Some(
+                    "JSON mirror of the MoneyView gRPC service. Enum fields hold the numbers \
+                     of the protobuf enums, dates are days since 1970-01-01.",
+                )),
+        )
+        .paths(paths)
+        .components(Some(
+            ComponentsBuilder::new()
+                .schemas_from_iter(schemas)
+                .security_scheme(
+                    BEARER,
+                    SecurityScheme::Http(Http::new(HttpAuthScheme::Bearer)),
+                )
+                .build(),
+        ))
+        .build()
+}
+
+/// Documents one route. `GET` requests are described as query parameters, the others as
+/// JSON body, `request` is `None` for RPCs taking `Empty`. `response` pairs content type
+/// and schema of a successful answer.
+fn operation(
+    method: HttpMethod,
+    rpc: &str,
+    request: Option<Ref>,
+    response: (&str, Ref),
+    authenticated: bool,
+) -> PathItem {
+    let (content_type, response) = response;
+    let mut operation = OperationBuilder::new()
+        .operation_id(Some(rpc))
+        .tag("MoneyView")
+        .response(
+            "200",
+            ResponseBuilder::new()
+                .description("Response message of the RPC")
+                .content(content_type, Content::new(Some(response)))
+                .build(),
+        )
+        .response(
+            "default",
+            ResponseBuilder::new()
+                .description("Failed call, the HTTP status follows the gRPC code")
+                .content(
+                    JSON,
+                    Content::new(Some(Ref::from_schema_name(ErrorBody::name()))),
+                )
+                .build(),
+        );
+    if authenticated {
+        operation = operation.security(SecurityRequirement::new(BEARER, Vec::<String>::new()));
+    }
+    operation = match (&method, request) {
+        (_, None) => operation,
+        (HttpMethod::Get, Some(request)) => operation.parameter(
+            ParameterBuilder::new()
+                .name("request")
+                .parameter_in(ParameterIn::Query)
+                .style(Some(ParameterStyle::Form))
+                .explode(Some(true))
+                .schema(Some(request))
+                .build(),
+        ),
+        (_, Some(request)) => operation.request_body(Some(
+            RequestBodyBuilder::new()
+                .required(Some(Required::True))
+                .content(JSON, Content::new(Some(request)))
+                .build(),
+        )),
+    };
+    PathItem::new(method, operation.build())
+}
+
+/// Reference to the schema of `T`, which is collected together with the schemas it uses.
+fn schema<T: ToSchema>(schemas: &mut Schemas) -> Ref {
+    schemas.push((T::name().into_owned(), T::schema()));
+    T::schemas(schemas);
+    Ref::from_schema_name(T::name())
+}
+
+fn optional_schema<T: ToSchema>(schemas: &mut Schemas) -> Option<Ref> {
+    (T::name() != Empty::name()).then(|| schema::<T>(schemas))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{Database, TransactionRecord, UserRecord};
+    use axum::body::Body;
+    use chrono::NaiveDate;
+    use std::collections::HashMap;
+    use surrealdb::sql::Thing;
+    use tokio_util::task::TaskTracker;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_openapi() {
+        let document = openapi();
+        let item = document.paths.get_path_item("/api/budgets").unwrap();
+        assert!(item.get.is_some());
+        assert!(item.post.is_some());
+        let search = document
+            .paths
+            .get_path_operation("/api/transactions/search", HttpMethod::Get)
+            .unwrap();
+        assert_eq!(search.parameters.as_ref().map(Vec::len), Some(1));
+        assert!(search.request_body.is_none());
+        let login = document
+            .paths
+            .get_path_operation("/api/login", HttpMethod::Post)
+            .unwrap();
+        assert!(login.security.is_none());
+
+        let schemas = document.components.unwrap().schemas;
+        for name in [
+            "TransactionFilter",
+            "BalanceRequest",
+            "ErrorBody",
+            "FieldViolation",
+        ] {
+            assert!(schemas.contains_key(name), "missing schema {}", name);
+        }
+    }
+
+    #[test]
+    fn test_http_status() {
+        assert_eq!(http_status(Code::InvalidArgument), StatusCode::BAD_REQUEST);
+        assert_eq!(http_status(Code::Unauthenticated), StatusCode::UNAUTHORIZED);
+        assert_eq!(http_status(Code::AlreadyExists), StatusCode::CONFLICT);
+        assert_eq!(
+            http_status(Code::Unavailable),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            http_status(Code::Unknown),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repeated_query_keys() {
+        let db = Database::memory().await;
+        let account_ids = ["giro", "savings", "depot"];
+        for account_id in account_ids {
+            db.save_transaction(TransactionRecord {
+                id: Thing::from(("transaction", account_id)),
+                account_id: account_id.to_string(),
+                date: NaiveDate::from_ymd_opt(2024, 9, 4).unwrap(),
+                total_amount: -20.0,
+                balance_after_transaction: -20.0,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        }
+        db.claim_accounts(account_ids.map(String::from).to_vec(), "anna")
+            .await
+            .unwrap();
+        let sessions = Sessions::new(
+            b"secret",
+            chrono::Duration::hours(1),
+            Vec::new(),
+            HashMap::new(),
+        );
+        let (token, _) = sessions
+            .issue(&UserRecord {
+                id: Thing::from(("user", "anna")),
+                user_name: "anna".to_string(),
+                password_hash: String::new(),
+                is_admin: false,
+                sessions_since: 0,
+            })
+            .unwrap();
+        let money_view = MoneyViewServer {
+            db: db.clone(),
+            jobs: TaskTracker::new(),
+            sessions: sessions.clone(),
+        };
+        let auth = AuthServer {
+            db,
+            sessions: sessions.clone(),
+        };
+        let router = Rest::new(Arc::new(money_view), Arc::new(auth), sessions)
+            .router()
+            .unwrap();
+
+        let response = router
+            .oneshot(
+                axum::http::Request::get(
+                    "/api/balances/history?account_ids=giro&account_ids=savings",
+                )
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("\"giro\""));
+        assert!(body.contains("\"savings\""));
+        assert!(!body.contains("\"depot\""));
+    }
+}
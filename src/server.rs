@@ -0,0 +1,162 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use axum::http::{HeaderName, HeaderValue, Method};
+use axum_server::tls_rustls::RustlsConfig;
+use serde::Deserialize;
+use tokio::signal::unix::{signal, SignalKind};
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
+use tracing::{info, warn};
+
+use crate::error::Result;
+
+/// Headers a gRPC-web client sends besides the ones in the CORS safelist.
+const GRPC_WEB_REQUEST_HEADERS: [&str; 5] = [
+    "authorization",
+    "content-type",
+    "grpc-timeout",
+    "x-grpc-web",
+    "x-user-agent",
+];
+/// Trailers a gRPC-web client has to read from the response.
+const GRPC_WEB_RESPONSE_HEADERS: [&str; 3] =
+    ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+const CORS_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Resolves on SIGINT (Ctrl+C) or SIGTERM.
+pub(crate) async fn shutdown_signal() {
+    let terminate = async {
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                warn!(error = %e, "cannot listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+/// Certificate and key of the in-process TLS termination, both PEM encoded.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct TlsFiles {
+    pub(crate) cert: PathBuf,
+    pub(crate) key: PathBuf,
+}
+
+impl TlsFiles {
+    pub(crate) async fn load(&self) -> Result<RustlsConfig> {
+        Ok(RustlsConfig::from_pem_file(&self.cert, &self.key).await?)
+    }
+
+    /// Reloads certificate and key whenever the process receives SIGHUP, so renewed
+    /// certificates are picked up without a restart. A failed reload keeps the old ones.
+    pub(crate) fn reload_on_hangup(self, config: RustlsConfig) -> Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match config.reload_from_pem_file(&self.cert, &self.key).await {
+                    Ok(()) => info!(cert = %self.cert.display(), "reloaded TLS certificate"),
+                    Err(e) => warn!(error = %e, "keeping old TLS certificate, reload failed"),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Cross-origin requests the server answers, each list either `["*"]` or explicit values.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct CorsSettings {
+    pub(crate) origins: Vec<String>,
+    pub(crate) methods: Vec<String>,
+    pub(crate) headers: Vec<String>,
+}
+
+impl CorsSettings {
+    /// Builds the CORS layer. Without configured origins only same-origin requests are
+    /// allowed, methods default to GET and POST and headers to the ones gRPC-web needs.
+    pub(crate) fn layer(&self) -> Result<CorsLayer> {
+        let origins = match list(&self.origins) {
+            None => AllowOrigin::list(Vec::<HeaderValue>::new()),
+            Some(origins) if origins == ["*"] => AllowOrigin::any(),
+            Some(origins) => AllowOrigin::list(
+                origins
+                    .iter()
+                    .map(|origin| HeaderValue::from_str(origin))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+        };
+        let methods = match list(&self.methods) {
+            Some(methods) if methods == ["*"] => AllowMethods::any(),
+            methods => AllowMethods::list(
+                methods
+                    .unwrap_or_else(|| vec!["GET", "POST"])
+                    .iter()
+                    .map(|method| Method::from_bytes(method.to_uppercase().as_bytes()))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+        };
+        let headers = match list(&self.headers) {
+            Some(headers) if headers == ["*"] => AllowHeaders::any(),
+            headers => AllowHeaders::list(
+                headers
+                    .unwrap_or_else(|| GRPC_WEB_REQUEST_HEADERS.to_vec())
+                    .iter()
+                    .map(|header| HeaderName::from_bytes(header.to_lowercase().as_bytes()))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+        };
+        Ok(CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(methods)
+            .allow_headers(headers)
+            .expose_headers(ExposeHeaders::list(
+                GRPC_WEB_RESPONSE_HEADERS.map(HeaderName::from_static),
+            ))
+            .max_age(CORS_MAX_AGE))
+    }
+}
+
+fn list(values: &[String]) -> Option<Vec<&str>> {
+    let values: Vec<&str> = values
+        .iter()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+    (!values.is_empty()).then_some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cors_settings() {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            list(&strings(&[" https://a.example", " ", "https://b.example"])),
+            Some(vec!["https://a.example", "https://b.example"])
+        );
+        assert_eq!(list(&strings(&[""])), None);
+
+        let settings = CorsSettings {
+            origins: strings(&["https://money.example"]),
+            methods: strings(&["get", "post", "options"]),
+            headers: Vec::new(),
+        };
+        assert!(settings.layer().is_ok());
+        let invalid = CorsSettings {
+            methods: strings(&["GET POST"]),
+            ..Default::default()
+        };
+        assert!(invalid.layer().is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashSet;
+
+use surrealdb::sql::Thing;
+
+use crate::database::TransactionRecord;
+
+/// Finds pairs of a debit on one own account and the matching credit on another.
+///
+/// The amounts have to cancel out, the bookings have to be at most `window_days` apart and
+/// the partner IBAN of at least one side has to point to the other account.
+/// Transactions in `linked` are already part of a pair and are skipped.
+pub(crate) fn detect<'a>(
+    transactions: &'a [TransactionRecord],
+    linked: &HashSet<Thing>,
+    window_days: i64,
+) -> Vec<(&'a TransactionRecord, &'a TransactionRecord)> {
+    let mut used: HashSet<&Thing> = linked.iter().collect();
+    let mut debits: Vec<&TransactionRecord> = transactions
+        .iter()
+        .filter(|t| t.total_amount < 0.0 && !used.contains(&t.id))
+        .collect();
+    debits.sort_by_key(|t| t.date);
+
+    let mut pairs = Vec::new();
+    for debit in debits {
+        let credit = transactions
+            .iter()
+            .filter(|credit| {
+                credit.account_id != debit.account_id
+                    && !used.contains(&credit.id)
+                    && (credit.total_amount + debit.total_amount).abs() < 0.005
+                    && (credit.date - debit.date).num_days().abs() <= window_days
+                    && (refers_to(&debit.partner_id, &credit.account_id)
+                        || refers_to(&credit.partner_id, &debit.account_id))
+            })
+            .min_by_key(|credit| (credit.date - debit.date).num_days().abs());
+        if let Some(credit) = credit {
+            used.insert(&debit.id);
+            used.insert(&credit.id);
+            pairs.push((debit, credit));
+        }
+    }
+    pairs
+}
+
+/// Whether a German IBAN belongs to an account id of the form `BLZ/account number`.
+fn refers_to(iban: &str, account_id: &str) -> bool {
+    let Some((bank_code, account_number)) = account_id.split_once('/') else {
+        return false;
+    };
+    let iban: String = iban.chars().filter(|c| !c.is_whitespace()).collect();
+    iban.len() == 22 && iban.ends_with(&format!("{}{:0>10}", bank_code, account_number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn booking(id: &str, account_id: &str, day: u32, amount: f32, iban: &str) -> TransactionRecord {
+        TransactionRecord {
+            id: Thing::from(("transaction", id)),
+            account_id: account_id.to_string(),
+            date: NaiveDate::from_ymd_opt(2024, 7, day).unwrap(),
+            total_amount: amount,
+            partner_id: iban.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        let transactions = vec![
+            booking(
+                "a",
+                "15091704/3000185000",
+                1,
+                -200.0,
+                "DE12150917040003001234",
+            ),
+            booking("b", "15091704/3001234", 2, 200.0, ""),
+            booking("c", "15091704/3001234", 2, 200.0, "DE99100100100123456789"),
+            booking(
+                "d",
+                "15091704/3000185000",
+                20,
+                -200.0,
+                "DE12150917040003001234",
+            ),
+        ];
+
+        let pairs = detect(&transactions, &HashSet::new(), 5);
+        let ids: Vec<(String, String)> = pairs
+            .iter()
+            .map(|(debit, credit)| (debit.id.id.to_raw(), credit.id.id.to_raw()))
+            .collect();
+        assert_eq!(ids, vec![("a".to_string(), "b".to_string())]);
+    }
+}